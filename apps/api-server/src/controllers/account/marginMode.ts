@@ -0,0 +1,34 @@
+import { marginModeSchema } from "@repo/schemas";
+import { kafkaRequestResponse } from "../../kafka/kafkaRequestResponse";
+import type { Request, Response } from "express";
+
+export const setMarginModeController = async (req: Request, res: Response) => {
+    try {
+        const userId = (req as any).user.id;
+        const result = marginModeSchema.safeParse(req.body);
+
+        if (!result.success) {
+            return res.status(400).json({ message: "Invalid input", errors: result.error.issues });
+        }
+
+        const engineResponse = await kafkaRequestResponse(
+            "margin-mode-request",
+            "margin-mode-response",
+            {
+                userId,
+                marginMode: result.data.marginMode,
+                timestamp: Date.now(),
+            },
+            undefined,
+            // Keyed by user so one account's mode changes stay in order
+            userId
+        );
+        if (engineResponse.status !== "accepted") {
+            return res.status(409).json(engineResponse);
+        }
+        return res.status(200).json(engineResponse);
+    } catch (error: any) {
+        console.error("Error in setMarginModeController:", error);
+        return res.status(500).json({ message: "Internal server error" });
+    }
+};
@@ -24,6 +24,7 @@ export const createOrderController = async (req: Request, res: Response) => {
             stopLossPercent,
             takeProfitPercent,
             tradeTerm,
+            termRollover,
             timeInForce,
             expiryTimestamp,
         } = result.data;
@@ -46,10 +47,14 @@ export const createOrderController = async (req: Request, res: Response) => {
                 stopLossPercent,
                 takeProfitPercent,
                 tradeTerm,
+                termRollover,
                 timeInForce,
                 expiryTimestamp: expiryTimestamp ?? null,
                 timestamp: Date.now(),
-            }
+            },
+            undefined,
+            // Keyed by user so a user's orders reach the engine in the order they were sent
+            userId
         );
 
         // 5. Ensure orderId is always present in response
@@ -0,0 +1,35 @@
+import { positionLeverageSchema } from "@repo/schemas";
+import { kafkaRequestResponse } from "../../kafka/kafkaRequestResponse";
+import type { Request, Response } from "express";
+
+export const positionLeverageController = async (req: Request, res: Response) => {
+    try {
+        const userId = (req as any).user.id;
+        const result = positionLeverageSchema.safeParse(req.body);
+
+        if (!result.success) {
+            return res.status(400).json({ message: "Invalid input", errors: result.error.issues });
+        }
+
+        const engineResponse = await kafkaRequestResponse(
+            "position-leverage-request",
+            "position-leverage-response",
+            {
+                userId,
+                orderId: result.data.orderId,
+                leverage: result.data.leverage,
+                timestamp: Date.now(),
+            },
+            undefined,
+            // Keyed by user so one account's balance moves stay in order
+            userId
+        );
+        if (engineResponse.status !== "accepted") {
+            return res.status(409).json(engineResponse);
+        }
+        return res.status(200).json(engineResponse);
+    } catch (error: any) {
+        console.error("Error in positionLeverageController:", error);
+        return res.status(500).json({ message: "Internal server error" });
+    }
+};
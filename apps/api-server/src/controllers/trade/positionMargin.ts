@@ -0,0 +1,36 @@
+import { positionMarginSchema } from "@repo/schemas";
+import { kafkaRequestResponse } from "../../kafka/kafkaRequestResponse";
+import type { Request, Response } from "express";
+
+export const positionMarginController = async (req: Request, res: Response) => {
+    try {
+        const userId = (req as any).user.id;
+        const result = positionMarginSchema.safeParse(req.body);
+
+        if (!result.success) {
+            return res.status(400).json({ message: "Invalid input", errors: result.error.issues });
+        }
+
+        const engineResponse = await kafkaRequestResponse(
+            "position-margin-request",
+            "position-margin-response",
+            {
+                userId,
+                orderId: result.data.orderId,
+                action: result.data.action,
+                amount: result.data.amount,
+                timestamp: Date.now(),
+            },
+            undefined,
+            // Keyed by user so one account's balance moves stay in order
+            userId
+        );
+        if (engineResponse.status !== "accepted") {
+            return res.status(409).json(engineResponse);
+        }
+        return res.status(200).json(engineResponse);
+    } catch (error: any) {
+        console.error("Error in positionMarginController:", error);
+        return res.status(500).json({ message: "Internal server error" });
+    }
+};
@@ -20,6 +20,9 @@ const setupConsumer = async () => {
     await consumer.subscribe({ topic: "trade-create-response", fromBeginning: false }); 
     await consumer.subscribe({ topic: "trade-close-response", fromBeginning: false }); 
     await consumer.subscribe({ topic: "holdings-query-response", fromBeginning: false });
+    await consumer.subscribe({ topic: "margin-mode-response", fromBeginning: false });
+    await consumer.subscribe({ topic: "position-margin-response", fromBeginning: false });
+    await consumer.subscribe({ topic: "position-leverage-response", fromBeginning: false });
 
     console.log("[Kafka Consumer] Subscribed, starting consumer...");
     await consumer.run({
@@ -58,7 +61,8 @@ export const kafkaRequestResponse = async (
     requestTopic: string,
     responseTopic: string,
     message: any,
-    timeout = 30000 // Increased timeout to 30 seconds
+    timeout = 30000, // Increased timeout to 30 seconds
+    key?: string // partition key; defaults to the correlation ID
 ): Promise<any> => {
     // Wait for consumer to be ready (with timeout)
     const maxWait = 30000; // 30 seconds
@@ -79,7 +83,7 @@ export const kafkaRequestResponse = async (
     await producer.connect();
     await producer.send({
         topic: requestTopic,
-        messages: [{ key: correlationId, value: JSON.stringify(messageWithCorrelationId) }],
+        messages: [{ key: key ?? correlationId, value: JSON.stringify(messageWithCorrelationId) }],
     });
 
     console.log(`Message published to ${requestTopic} with correlationId: ${correlationId}`);
@@ -0,0 +1,9 @@
+import { Router } from "express";
+import { setMarginModeController } from "../../../controllers/account/marginMode";
+import { requireAuth } from "../../../middleware/auth";
+
+const accountRouter = Router();
+
+accountRouter.post("/margin-mode", requireAuth, setMarginModeController); // POST /api/v1/account/margin-mode
+
+export default accountRouter;
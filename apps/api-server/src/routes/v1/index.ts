@@ -6,6 +6,7 @@ import authRouter from "./auth/index"; // Import the auth sub-router
 import pricesRouter from "./prices/index"; // Import the prices sub-router
 import balanceRouter from "./balance";
 import tradeRouter from "./trade";
+import accountRouter from "./account";
 
 const v1Router = Router();
 
@@ -21,4 +22,7 @@ v1Router.use("/balance", balanceRouter);
 //Mount trade router
 v1Router.use("/trade", tradeRouter);
 
+//Mount account router
+v1Router.use("/account", accountRouter);
+
 export default v1Router;
\ No newline at end of file
@@ -1,11 +1,15 @@
 import { Router } from "express";
 import { createOrderController } from "../../../controllers/trade/createOrder";
 import { closeOrderController } from "../../../controllers/trade/closeOrder";
+import { positionMarginController } from "../../../controllers/trade/positionMargin";
+import { positionLeverageController } from "../../../controllers/trade/positionLeverage";
 import { requireAuth } from "../../../middleware/auth";
 
 const tradeRouter = Router();
 
 tradeRouter.post("/create", requireAuth, createOrderController);
 tradeRouter.post("/close", requireAuth, closeOrderController);
+tradeRouter.post("/margin", requireAuth, positionMarginController); // add or remove margin on an open position
+tradeRouter.post("/leverage", requireAuth, positionLeverageController); // change leverage on an open position
 
 export default tradeRouter;
\ No newline at end of file
@@ -5,6 +5,8 @@ export const balanceRequestHandler = async (parsedMessage: any) => {
     try {
         console.log("Received balance-request message:", parsedMessage);
         const user_id = parsedMessage.user_id || parsedMessage.userId;
+        // Echoed back so the engine can tell reconciliation checks from pending-trade loads
+        const requestId = parsedMessage.requestId;
         const balanceRecord = await prisma.balance.findUnique({ where: { userId: user_id } });
         console.log("Balance record from DB for user:", user_id, balanceRecord);
         const toBigIntString = (val: any): string => {
@@ -35,7 +37,7 @@ export const balanceRequestHandler = async (parsedMessage: any) => {
             messages: [
                 {
                     key: user_id,
-                    value: JSON.stringify({ user_id, balance }),
+                    value: JSON.stringify({ user_id, balance, requestId }),
                 },
             ],
         });
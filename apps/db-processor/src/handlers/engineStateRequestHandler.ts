@@ -0,0 +1,127 @@
+import { producer } from "@repo/kafka";
+import { db as prisma } from "@repo/db";
+import { HaltScope, TradeStatus } from "@repo/db/generated/prisma";
+
+// Rows per engine-state-response message, keeping each well under the broker's size limit
+const PAGE_SIZE = 500;
+
+// Trades the engine still holds: resting limit orders (OPEN) and open positions
+const LIVE_STATUSES = [TradeStatus.OPEN, TradeStatus.MATCHED, TradeStatus.FILLED];
+
+const toStringOrNull = (val: bigint | null | undefined): string | null =>
+    val === undefined || val === null ? null : val.toString();
+
+export const engineStateRequestHandler = async (message: any) => {
+    const { requestId } = message;
+    if (!requestId) {
+        console.error("Invalid engine-state-request: missing requestId");
+        return;
+    }
+
+    try {
+        type Page = {
+            balances?: unknown[];
+            trades?: unknown[];
+            holdings?: unknown[];
+            halts?: unknown[];
+            done: boolean;
+        };
+        const sendPage = async (page: Page) => {
+            await producer.send({
+                topic: "engine-state-response",
+                messages: [
+                    {
+                        // One key keeps every page on one partition, in order
+                        key: requestId,
+                        value: JSON.stringify({ requestId, ...page }),
+                    },
+                ],
+            });
+        };
+
+        const balances = await prisma.balance.findMany({ orderBy: { userId: "asc" } });
+        for (let i = 0; i < balances.length; i += PAGE_SIZE) {
+            await sendPage({
+                balances: balances.slice(i, i + PAGE_SIZE).map((balance) => ({
+                    userId: balance.userId,
+                    balance: balance.amount.toString(),
+                    marginMode: balance.marginMode.toLowerCase(),
+                })),
+                done: false,
+            });
+        }
+
+        const holdings = await prisma.holdings.findMany({
+            orderBy: [{ userId: "asc" }, { asset: "asc" }],
+        });
+        for (let i = 0; i < holdings.length; i += PAGE_SIZE) {
+            await sendPage({
+                holdings: holdings.slice(i, i + PAGE_SIZE).map((holding) => ({
+                    userId: holding.userId,
+                    asset: holding.asset,
+                    quantity: holding.quantity.toString(),
+                })),
+                done: false,
+            });
+        }
+
+        const halts = await prisma.tradingHalt.findMany();
+        if (halts.length > 0) {
+            await sendPage({
+                halts: halts.map((halt) => ({
+                    scope: halt.scope.toLowerCase(),
+                    target: halt.scope === HaltScope.GLOBAL ? null : halt.target,
+                    allowCancels: halt.allowCancels,
+                    allowCloses: halt.allowCloses,
+                    reason: halt.reason,
+                    since: halt.since.getTime(),
+                })),
+                done: false,
+            });
+        }
+
+        // Oldest first, so resting orders keep their time priority on the rebuilt book. A row
+        // no longer live can still carry the resting remainder of a partially filled order.
+        const trades = await prisma.trade.findMany({
+            where: {
+                OR: [{ status: { in: LIVE_STATUSES } }, { restingQuantity: { gt: 0 } }],
+            },
+            orderBy: [{ createdAt: "asc" }, { id: "asc" }],
+        });
+        for (let i = 0; i < trades.length; i += PAGE_SIZE) {
+            await sendPage({
+                trades: trades.slice(i, i + PAGE_SIZE).map((trade) => ({
+                    id: trade.id,
+                    userId: trade.userId,
+                    asset: trade.asset,
+                    side: trade.side,
+                    status: trade.status,
+                    orderType: trade.orderType,
+                    quantity: toStringOrNull(trade.quantity) ?? "0",
+                    margin: trade.margin.toString(),
+                    lockedMargin: toStringOrNull(trade.lockedMargin),
+                    restingQuantity: toStringOrNull(trade.restingQuantity),
+                    restingMargin: toStringOrNull(trade.restingMargin),
+                    leverage: trade.leverage,
+                    entryPrice: toStringOrNull(trade.entryPrice),
+                    limitPrice: toStringOrNull(trade.limitPrice),
+                    stopLossPercent: trade.stopLossPercent,
+                    takeProfitPercent: trade.takeProfitPercent,
+                    tradeTerm: trade.tradeTerm,
+                    termRollover: trade.termRollover,
+                    termExpiresAt: trade.termExpiresAt ? trade.termExpiresAt.getTime() : null,
+                    createdAt: trade.createdAt.getTime(),
+                })),
+                done: false,
+            });
+        }
+
+        await sendPage({ done: true });
+        console.log(
+            `Engine state sent for request ${requestId}: ${balances.length} balances, ` +
+            `${holdings.length} holdings, ${halts.length} halts, ${trades.length} trades`
+        );
+    } catch (error) {
+        console.error("Error in engineStateRequestHandler:", error);
+    }
+};
@@ -3,7 +3,8 @@ import { producer } from "@repo/kafka";
 
 export const holdingsQueryHandler = async (message: any) => {
     try {
-        const { userId, asset } = message;
+        // requestId is echoed back so the engine can tell reconciliation checks apart
+        const { userId, asset, requestId } = message;
 
         if (!userId || !asset) {
             console.error("Invalid message: Missing userId or asset");
@@ -60,6 +61,7 @@ export const holdingsQueryHandler = async (message: any) => {
                         heldQuantity,
                         userId,
                         asset,
+                        requestId,
                     }),
                 },
             ],
@@ -0,0 +1,31 @@
+import { db as prisma } from "@repo/db";
+import { MarginMode } from "@repo/db/generated/prisma";
+
+// Records each margin mode change the engine accepts, so a fresh engine loads it back
+export const marginModeResponseHandler = async (message: any) => {
+    const { userId, status, marginMode } = message;
+    if (status !== "accepted") {
+        return;
+    }
+    const prismaMode = typeof marginMode === "string"
+        ? MarginMode[marginMode.toUpperCase() as keyof typeof MarginMode]
+        : undefined;
+    if (!userId || !prismaMode) {
+        console.error("Invalid margin-mode-response: missing userId or unknown margin mode");
+        return;
+    }
+
+    try {
+        const { count } = await prisma.balance.updateMany({
+            where: { userId },
+            data: { marginMode: prismaMode },
+        });
+        if (count === 0) {
+            console.warn(`No balance row to record margin mode ${prismaMode} for user ${userId}`);
+            return;
+        }
+        console.log(`Recorded margin mode ${prismaMode} for user ${userId}`);
+    } catch (error) {
+        console.error("Error in marginModeResponseHandler:", error);
+    }
+};
@@ -1,5 +1,5 @@
 import { db as prisma } from "@repo/db";
-import { Prisma, TradeStatus } from "@repo/db/generated/prisma";
+import { Prisma, TradeStatus, TradeTerm } from "@repo/db/generated/prisma";
 
 export const tradeOutcomeHandler = async (message: any) => {
     try {
@@ -86,9 +86,23 @@ export const tradeOutcomeHandler = async (message: any) => {
             message.lockedMargin ?? message.locked_margin,
             "lockedMargin"
         );
+        // Unfilled remainder of a partially filled order, which shares its id with this row
+        const parsedRestingQuantity = parseBigIntField(message.restingQuantity, "restingQuantity");
+        const parsedRestingMargin = parseBigIntField(message.restingMargin, "restingMargin");
         const parsedStopLossPercent = parseIntField(message.stopLossPercent ?? message.stop_loss_percent);
         const parsedTakeProfitPercent = parseIntField(message.takeProfitPercent ?? message.take_profit_percent);
 
+        // The position's term as the engine holds it after this update
+        const termPayload: any = {};
+        if (message.term && typeof message.term.term === "string" && TradeTerm[message.term.term as keyof typeof TradeTerm]) {
+            termPayload.tradeTerm = TradeTerm[message.term.term as keyof typeof TradeTerm];
+            termPayload.termRollover = Boolean(message.term.rollover);
+            const expiresAt = Number(message.term.expiresAt);
+            if (Number.isFinite(expiresAt)) {
+                termPayload.termExpiresAt = new Date(expiresAt);
+            }
+        }
+
         if (parsedQuantity === undefined || parsedEntryPrice === undefined) {
             console.error("Unable to process trade outcome due to missing required numeric fields.");
             return;
@@ -113,6 +127,12 @@ export const tradeOutcomeHandler = async (message: any) => {
         if (parsedLockedMargin !== undefined) {
             updatePayload.lockedMargin = parsedLockedMargin;
         }
+        if (parsedRestingQuantity !== undefined) {
+            updatePayload.restingQuantity = parsedRestingQuantity;
+        }
+        if (parsedRestingMargin !== undefined) {
+            updatePayload.restingMargin = parsedRestingMargin;
+        }
         if (prismaOrderType) {
             updatePayload.orderType = prismaOrderType;
         }
@@ -125,6 +145,7 @@ export const tradeOutcomeHandler = async (message: any) => {
         if (parsedTakeProfitPercent !== undefined) {
             updatePayload.takeProfitPercent = parsedTakeProfitPercent;
         }
+        Object.assign(updatePayload, termPayload);
         if (userId) {
             updatePayload.user = { connect: { id: userId } };
         }
@@ -147,6 +168,12 @@ export const tradeOutcomeHandler = async (message: any) => {
         if (parsedLockedMargin !== undefined) {
             createPayload.lockedMargin = parsedLockedMargin;
         }
+        if (parsedRestingQuantity !== undefined) {
+            createPayload.restingQuantity = parsedRestingQuantity;
+        }
+        if (parsedRestingMargin !== undefined) {
+            createPayload.restingMargin = parsedRestingMargin;
+        }
         if (prismaOrderType) {
             createPayload.orderType = prismaOrderType;
         }
@@ -159,6 +186,7 @@ export const tradeOutcomeHandler = async (message: any) => {
         if (parsedTakeProfitPercent !== undefined) {
             createPayload.takeProfitPercent = parsedTakeProfitPercent;
         }
+        Object.assign(createPayload, termPayload);
         if (userId) {
             createPayload.user = { connect: { id: userId } };
         }
@@ -0,0 +1,39 @@
+import { db as prisma } from "@repo/db";
+import { HaltScope } from "@repo/db/generated/prisma";
+
+// Keeps the TradingHalt table in step with the engine's "trading-halt" transitions, so a
+// fresh engine loads the halts still in force
+export const tradingHaltHandler = async (message: any) => {
+    const { scope, status, allowCancels, allowCloses, reason, timestamp } = message;
+    const prismaScope = typeof scope === "string"
+        ? HaltScope[scope.toUpperCase() as keyof typeof HaltScope]
+        : undefined;
+    if (!prismaScope || !status) {
+        console.error("Invalid trading-halt message: missing or unknown scope or status");
+        return;
+    }
+    // Global halts have no target; one row stands for them
+    const target: string = message.target ?? "global";
+
+    try {
+        if (status === "lifted") {
+            await prisma.tradingHalt.deleteMany({ where: { scope: prismaScope, target } });
+            console.log(`Lifted ${prismaScope} halt on ${target}`);
+            return;
+        }
+        const halt = {
+            allowCancels: Boolean(allowCancels),
+            allowCloses: Boolean(allowCloses),
+            reason: reason ?? null,
+            since: new Date(timestamp ?? Date.now()),
+        };
+        await prisma.tradingHalt.upsert({
+            where: { scope_target: { scope: prismaScope, target } },
+            update: halt,
+            create: { scope: prismaScope, target, ...halt },
+        });
+        console.log(`Recorded ${prismaScope} halt on ${target} (${status})`);
+    } catch (error) {
+        console.error("Error in tradingHaltHandler:", error);
+    }
+};
@@ -10,6 +10,9 @@ import { balanceQueryHandler } from "./handlers/balanceQueryHandler";
 import { holdingsQueryHandler } from "./handlers/holdingsQueryHandler";
 import { balanceRequestHandler } from "./handlers/balanceRequestHandler";
 import { tradeOutcomeHandler } from "./handlers/tradeOutcomeHandler";
+import { engineStateRequestHandler } from "./handlers/engineStateRequestHandler";
+import { marginModeResponseHandler } from "./handlers/marginModeResponseHandler";
+import { tradingHaltHandler } from "./handlers/tradingHaltHandler";
 
 const messageHandler = async (topic: string, message: any) => {
     // Handles incoming Kafka messages based on their topic.
@@ -50,6 +53,15 @@ const messageHandler = async (topic: string, message: any) => {
             case "trade-outcome":
                 await tradeOutcomeHandler(parsedMessage);
                 break;
+            case "engine-state-request":
+                await engineStateRequestHandler(parsedMessage);
+                break;
+            case "margin-mode-response":
+                await marginModeResponseHandler(parsedMessage);
+                break;
+            case "trading-halt":
+                await tradingHaltHandler(parsedMessage);
+                break;
             default:
                 console.warn(`Unknown topic: ${topic}`);
         }
@@ -76,7 +88,10 @@ const startDbProcessor = async () => {
                 "balance-query-request",
                 "holdings-request",
                 "balance-request",
-                "trade-outcome"
+                "trade-outcome",
+                "engine-state-request",
+                "margin-mode-response",
+                "trading-halt"
             ];
 
         await setupKafkaConsumer(topics, messageHandler);
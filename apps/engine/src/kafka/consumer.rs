@@ -1,17 +1,97 @@
-use crate::modules::price_updater::handle_price_update;
-use crate::modules::processor::process_trade_create;
-use crate::modules::state::SharedEngineState;
-use crate::modules::types::CreateTradeRequest;
+use crate::kafka::producer;
+use crate::modules::candles::process_candle_query;
+use crate::modules::config::CONFIG;
+use crate::modules::journal::JournalEvent;
+use crate::modules::price_aggregator::process_price_query;
+use crate::modules::reconciliation::RECONCILIATION_PREFIX;
+use crate::modules::sequencer::{submit, submit_price_tick, EventSender, MarketViewReceiver};
+use crate::modules::state::EngineState;
+use crate::modules::state_load::{apply_state_load_page, complete_state_load};
+use crate::modules::types::{
+    AdminRequest, CandleQueryRequest, CloseTradeRequest, CreateTradeRequest, LeverageChangeRequest,
+    MarginModeRequest, PositionMarginRequest, PriceQueryRequest, ReconciliationKey,
+    ReconciliationKind, StateLoadResponse,
+};
 use rdkafka::config::ClientConfig;
 use rdkafka::consumer::{Consumer, StreamConsumer};
 use rdkafka::message::Message;
 use serde_json;
+use tokio::time::{timeout_at, Duration, Instant};
+use uuid::Uuid;
+
+/// Build a fresh engine's state from the DB: ask db-processor on "engine-state-request" and
+/// apply the pages it answers with on "engine-state-response". Asks again if no complete
+/// answer arrives in time; returns only once the last page has been applied.
+pub async fn load_state_from_db() -> EngineState {
+    println!("Starting State Load Consumer...");
+
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "engine-state-load-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .expect("State Load Consumer creation failed");
+
+    consumer
+        .subscribe(&["engine-state-response"])
+        .expect("Can't subscribe to engine-state-response");
+
+    loop {
+        let request_id = Uuid::new_v4().to_string();
+        producer::send_engine_state_request(&request_id).await;
+
+        let mut engine_state = EngineState::new();
+        let mut pages = 0;
+        let deadline = Instant::now() + Duration::from_millis(CONFIG.state_load_timeout_ms as u64);
+        loop {
+            match timeout_at(deadline, consumer.recv()).await {
+                Ok(Ok(message)) => {
+                    let Some(Ok(payload)) = message.payload_view::<str>() else {
+                        continue;
+                    };
+                    match serde_json::from_str::<StateLoadResponse>(payload) {
+                        // Answers to earlier requests are left alone
+                        Ok(page) if page.request_id == request_id => {
+                            apply_state_load_page(&mut engine_state, &page);
+                            pages += 1;
+                            if page.done {
+                                complete_state_load(
+                                    &mut engine_state,
+                                    chrono::Utc::now().timestamp_millis(),
+                                );
+                                println!(
+                                    "Loaded state from the database in {} pages: {} balances, {} open trades, {} order books",
+                                    pages,
+                                    engine_state.balances.len(),
+                                    engine_state.open_trades.len(),
+                                    engine_state.order_books.len()
+                                );
+                                return engine_state;
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            println!("Failed to parse state load response: {}", e);
+                        }
+                    }
+                }
+                Ok(Err(e)) => {
+                    println!("Error receiving state load message: {}", e);
+                }
+                Err(_) => {
+                    println!(
+                        "No complete state load answer for {} after {}ms, asking again",
+                        request_id, CONFIG.state_load_timeout_ms
+                    );
+                    break;
+                }
+            }
+        }
+    }
+}
 
 /// Consumer for fast trade requests (subscribed only to "trade-create-request")
-pub async fn consume_trade_requests(
-    state: SharedEngineState,
-    tx: tokio::sync::mpsc::Sender<String>,
-) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn consume_trade_requests(events: EventSender) -> Result<(), Box<dyn std::error::Error>> {
     println!("Starting Trade Request Consumer...");
 
     let consumer: StreamConsumer = ClientConfig::new()
@@ -35,11 +115,8 @@ pub async fn consume_trade_requests(
                     match serde_json::from_str::<CreateTradeRequest>(payload) {
                         Ok(req) => {
                             println!("Received trade create request: {:?}", req);
-                            let state_clone = state.clone();
-                            let tx_clone = tx.clone();
-                            tokio::spawn(async move {
-                                process_trade_create(state_clone, req, tx_clone).await;
-                            });
+                            // Handed to the sequencer in arrival order
+                            submit(&events, JournalEvent::TradeCreate(req)).await;
                         }
                         Err(e) => {
                             println!("Failed to parse trade create request: {}", e);
@@ -54,11 +131,49 @@ pub async fn consume_trade_requests(
     }
 }
 
-/// Consumer for slow price updates (subscribed only to "price-updates")
-pub async fn consume_price_updates(
-    state: SharedEngineState,
-    tx: tokio::sync::mpsc::Sender<String>,
+/// Consumer for position closes and order cancels (subscribed only to "trade-close-request")
+pub async fn consume_trade_close_requests(
+    events: EventSender,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Starting Trade Close Consumer...");
+
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "engine-trade-close-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .expect("Trade Close Consumer creation failed");
+
+    consumer
+        .subscribe(&["trade-close-request"])
+        .expect("Can't subscribe to trade-close-request");
+
+    println!("Trade Close Consumer started, waiting for messages...");
+
+    loop {
+        match consumer.recv().await {
+            Ok(message) => {
+                if let Some(Ok(payload)) = message.payload_view::<str>() {
+                    match serde_json::from_str::<CloseTradeRequest>(payload) {
+                        Ok(req) => {
+                            println!("Received trade close request: {:?}", req);
+                            submit(&events, JournalEvent::TradeClose(req)).await;
+                        }
+                        Err(e) => {
+                            println!("Failed to parse trade close request: {}", e);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                println!("Error receiving trade close message: {}", e);
+            }
+        }
+    }
+}
+
+/// Consumer for slow price updates (subscribed only to "price-updates")
+pub async fn consume_price_updates(events: EventSender) -> Result<(), Box<dyn std::error::Error>> {
     println!("Starting Price Update Consumer...");
 
     let consumer: StreamConsumer = ClientConfig::new()
@@ -78,8 +193,7 @@ pub async fn consume_price_updates(
         match consumer.recv().await {
             Ok(message) => {
                 if let Some(Ok(payload)) = message.payload_view::<str>() {
-                    // Unwrap the Result
-                    handle_price_update(payload, state.clone()).await;
+                    submit_price_tick(&events, payload).await;
                 }
             }
             Err(e) => {
@@ -90,8 +204,7 @@ pub async fn consume_price_updates(
 }
 
 pub async fn consume_balance_responses(
-    state: SharedEngineState,
-    tx: tokio::sync::mpsc::Sender<String>,
+    events: EventSender,
 ) -> Result<(), Box<dyn std::error::Error>> {
     println!("Starting Balance Response Consumer...");
 
@@ -129,31 +242,21 @@ pub async fn consume_balance_responses(
                                 user_id, balance
                             );
 
-                            let mut engine_state = state.lock().await;
-                            engine_state.balances.insert(user_id.clone(), balance);
-                            if let Some(updated_balance) = engine_state.balances.get(&user_id) {
-                                println!(
-                                    "Engine state updated balance for user {}: {}",
-                                    user_id, updated_balance
-                                );
-                            }
-
-                            // Process pending trades for this user, if any
-                            if let Some(mut pending) = engine_state.pending_trades.remove(&user_id)
-                            {
-                                for trade_req in pending.drain(..) {
-                                    // Drop the lock before calling process_trade_create to avoid deadlock
-                                    drop(engine_state);
-                                    crate::modules::processor::process_trade_create(
-                                        state.clone(),
-                                        trade_req,
-                                        tx.clone(),
-                                    )
-                                    .await;
-                                    engine_state = state.lock().await;
+                            let event = match resp.get("requestId").and_then(|id| id.as_str()) {
+                                Some(run_id) if run_id.starts_with(RECONCILIATION_PREFIX) => {
+                                    JournalEvent::ReconciliationAnswer {
+                                        run_id: run_id.to_string(),
+                                        key: ReconciliationKey {
+                                            kind: ReconciliationKind::Balance,
+                                            user_id,
+                                            asset: None,
+                                        },
+                                        database: balance,
+                                    }
                                 }
-                            }
-                            // === END INSERT ===
+                                _ => JournalEvent::BalanceResponse { user_id, balance },
+                            };
+                            submit(&events, event).await;
                         }
                     }
                 }
@@ -166,8 +269,7 @@ pub async fn consume_balance_responses(
 }
 
 pub async fn consume_holdings_responses(
-    state: SharedEngineState,
-    tx: tokio::sync::mpsc::Sender<String>,
+    events: EventSender,
 ) -> Result<(), Box<dyn std::error::Error>> {
     println!("Starting Holdings Response Consumer...");
 
@@ -208,34 +310,25 @@ pub async fn consume_holdings_responses(
                                 user_id, asset, held_quantity
                             );
 
-                            let mut engine_state = state.lock().await;
-                            engine_state
-                                .holdings
-                                .insert((user_id.clone(), asset.clone()), held_quantity);
-                            if let Some(updated_holding) =
-                                engine_state.holdings.get(&(user_id.clone(), asset.clone()))
-                            {
-                                println!(
-                                    "Engine state updated holdings for user {} asset {}: {}",
-                                    user_id, asset, updated_holding
-                                );
-                            }
-
-                            // Process pending trades for this user, if any
-                            if let Some(mut pending) = engine_state.pending_trades.remove(&user_id)
-                            {
-                                for trade_req in pending.drain(..) {
-                                    // Drop the lock before calling process_trade_create to avoid deadlock
-                                    drop(engine_state);
-                                    crate::modules::processor::process_trade_create(
-                                        state.clone(),
-                                        trade_req,
-                                        tx.clone(),
-                                    )
-                                    .await;
-                                    engine_state = state.lock().await;
+                            let event = match resp.get("requestId").and_then(|id| id.as_str()) {
+                                Some(run_id) if run_id.starts_with(RECONCILIATION_PREFIX) => {
+                                    JournalEvent::ReconciliationAnswer {
+                                        run_id: run_id.to_string(),
+                                        key: ReconciliationKey {
+                                            kind: ReconciliationKind::Holdings,
+                                            user_id,
+                                            asset: Some(asset),
+                                        },
+                                        database: held_quantity,
+                                    }
                                 }
-                            }
+                                _ => JournalEvent::HoldingsResponse {
+                                    user_id,
+                                    asset,
+                                    held_quantity,
+                                },
+                            };
+                            submit(&events, event).await;
                         }
                     }
                 }
@@ -246,3 +339,249 @@ pub async fn consume_holdings_responses(
         }
     }
 }
+
+/// Consumer for account margin mode changes (subscribed only to "margin-mode-request")
+pub async fn consume_margin_mode_requests(
+    events: EventSender,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Starting Margin Mode Consumer...");
+
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "engine-margin-mode-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .expect("Margin Mode Consumer creation failed");
+
+    consumer
+        .subscribe(&["margin-mode-request"])
+        .expect("Can't subscribe to margin-mode-request");
+
+    println!("Margin Mode Consumer started, waiting for messages...");
+
+    loop {
+        match consumer.recv().await {
+            Ok(message) => {
+                if let Some(Ok(payload)) = message.payload_view::<str>() {
+                    match serde_json::from_str::<MarginModeRequest>(payload) {
+                        Ok(req) => {
+                            println!("Received margin mode request: {:?}", req);
+                            submit(&events, JournalEvent::MarginMode(req)).await;
+                        }
+                        Err(e) => {
+                            println!("Failed to parse margin mode request: {}", e);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                println!("Error receiving margin mode message: {}", e);
+            }
+        }
+    }
+}
+
+/// Consumer for margin top-ups and withdrawals on open positions (subscribed only to "position-margin-request")
+pub async fn consume_position_margin_requests(
+    events: EventSender,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Starting Position Margin Consumer...");
+
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "engine-position-margin-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .expect("Position Margin Consumer creation failed");
+
+    consumer
+        .subscribe(&["position-margin-request"])
+        .expect("Can't subscribe to position-margin-request");
+
+    println!("Position Margin Consumer started, waiting for messages...");
+
+    loop {
+        match consumer.recv().await {
+            Ok(message) => {
+                if let Some(Ok(payload)) = message.payload_view::<str>() {
+                    match serde_json::from_str::<PositionMarginRequest>(payload) {
+                        Ok(req) => {
+                            println!("Received position margin request: {:?}", req);
+                            submit(&events, JournalEvent::PositionMargin(req)).await;
+                        }
+                        Err(e) => {
+                            println!("Failed to parse position margin request: {}", e);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                println!("Error receiving position margin message: {}", e);
+            }
+        }
+    }
+}
+
+/// Consumer for leverage changes on open positions (subscribed only to "position-leverage-request")
+pub async fn consume_leverage_requests(
+    events: EventSender,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Starting Leverage Consumer...");
+
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "engine-leverage-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .expect("Leverage Consumer creation failed");
+
+    consumer
+        .subscribe(&["position-leverage-request"])
+        .expect("Can't subscribe to position-leverage-request");
+
+    println!("Leverage Consumer started, waiting for messages...");
+
+    loop {
+        match consumer.recv().await {
+            Ok(message) => {
+                if let Some(Ok(payload)) = message.payload_view::<str>() {
+                    match serde_json::from_str::<LeverageChangeRequest>(payload) {
+                        Ok(req) => {
+                            println!("Received leverage change request: {:?}", req);
+                            submit(&events, JournalEvent::Leverage(req)).await;
+                        }
+                        Err(e) => {
+                            println!("Failed to parse leverage change request: {}", e);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                println!("Error receiving leverage message: {}", e);
+            }
+        }
+    }
+}
+
+/// Consumer for aggregated price lookups (subscribed only to "price-query-request")
+pub async fn consume_price_queries(
+    view: MarketViewReceiver,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Starting Price Query Consumer...");
+
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "engine-price-query-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .expect("Price Query Consumer creation failed");
+
+    consumer
+        .subscribe(&["price-query-request"])
+        .expect("Can't subscribe to price-query-request");
+
+    println!("Price Query Consumer started, waiting for messages...");
+
+    loop {
+        match consumer.recv().await {
+            Ok(message) => {
+                if let Some(Ok(payload)) = message.payload_view::<str>() {
+                    match serde_json::from_str::<PriceQueryRequest>(payload) {
+                        Ok(req) => {
+                            println!("Received price query: {:?}", req);
+                            let market = view.borrow().clone();
+                            process_price_query(&market, req).await;
+                        }
+                        Err(e) => {
+                            println!("Failed to parse price query: {}", e);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                println!("Error receiving price query message: {}", e);
+            }
+        }
+    }
+}
+
+/// Consumer for chart snapshot lookups (subscribed only to "candle-query-request")
+pub async fn consume_candle_queries(
+    view: MarketViewReceiver,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Starting Candle Query Consumer...");
+
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "engine-candle-query-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .expect("Candle Query Consumer creation failed");
+
+    consumer
+        .subscribe(&["candle-query-request"])
+        .expect("Can't subscribe to candle-query-request");
+
+    println!("Candle Query Consumer started, waiting for messages...");
+
+    loop {
+        match consumer.recv().await {
+            Ok(message) => {
+                if let Some(Ok(payload)) = message.payload_view::<str>() {
+                    match serde_json::from_str::<CandleQueryRequest>(payload) {
+                        Ok(req) => {
+                            println!("Received candle query: {:?}", req);
+                            let market = view.borrow().clone();
+                            process_candle_query(&market, req).await;
+                        }
+                        Err(e) => {
+                            println!("Failed to parse candle query: {}", e);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                println!("Error receiving candle query message: {}", e);
+            }
+        }
+    }
+}
+
+/// Consumer for asset registry administration (subscribed only to "engine-admin")
+pub async fn consume_admin_requests(events: EventSender) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Starting Admin Consumer...");
+
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "engine-admin-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .expect("Admin Consumer creation failed");
+
+    consumer
+        .subscribe(&["engine-admin"])
+        .expect("Can't subscribe to engine-admin");
+
+    println!("Admin Consumer started, waiting for messages...");
+
+    loop {
+        match consumer.recv().await {
+            Ok(message) => {
+                if let Some(Ok(payload)) = message.payload_view::<str>() {
+                    match serde_json::from_str::<AdminRequest>(payload) {
+                        Ok(req) => {
+                            println!("Received admin request: {:?}", req);
+                            submit(&events, JournalEvent::Admin(req)).await;
+                        }
+                        Err(e) => {
+                            println!("Failed to parse admin request: {}", e);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                println!("Error receiving admin message: {}", e);
+            }
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use once_cell::sync::Lazy;
+use rdkafka::message::ToBytes;
+use rdkafka::producer::future_producer::OwnedDeliveryResult;
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use serde::de::Error as SerdeDeError;
 use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -14,6 +17,25 @@ pub static PRODUCER: Lazy<Arc<FutureProducer>> = Lazy::new(|| {
     Arc::new(producer)
 });
 
+// Set while the journal is replayed at startup: replayed inputs were already answered
+static REPLAYING: AtomicBool = AtomicBool::new(false);
+
+pub fn set_replaying(replaying: bool) {
+    REPLAYING.store(replaying, Ordering::SeqCst);
+}
+
+/// Produce `record`, or nothing while the journal is being replayed.
+async fn send<K, P>(record: FutureRecord<'_, K, P>) -> Option<OwnedDeliveryResult>
+where
+    K: ToBytes + ?Sized,
+    P: ToBytes + ?Sized,
+{
+    if REPLAYING.load(Ordering::SeqCst) {
+        return None;
+    }
+    Some(PRODUCER.send(record, Duration::from_secs(0)).await)
+}
+
 /// Send a balance request for a user to the "balance-request" topic.
 pub async fn send_balance_request(user_id: &str) -> Result<(), Box<dyn std::error::Error>> {
     let payload = json!({
@@ -25,9 +47,10 @@ pub async fn send_balance_request(user_id: &str) -> Result<(), Box<dyn std::erro
         .key(user_id)
         .payload(&payload);
 
-    match PRODUCER.send(record, Duration::from_secs(0)).await {
-        Ok(_) => println!("Balance request sent for user: {}", user_id),
-        Err((e, _)) => println!("Failed to produce balance request: {}", e),
+    match send(record).await {
+        Some(Ok(_)) => println!("Balance request sent for user: {}", user_id),
+        Some(Err((e, _))) => println!("Failed to produce balance request: {}", e),
+        None => {} // replaying the journal
     }
 
     Ok(())
@@ -48,25 +71,224 @@ pub async fn send_holdings_request(
         .key(user_id)
         .payload(&payload);
 
-    match PRODUCER.send(record, Duration::from_secs(0)).await {
-        Ok(_) => println!(
+    match send(record).await {
+        Some(Ok(_)) => println!(
             "Holdings request sent for user: {}, asset: {}",
             user_id, asset
         ),
-        Err((e, _)) => println!("Failed to produce holdings request: {}", e),
+        Some(Err((e, _))) => println!("Failed to produce holdings request: {}", e),
+        None => {} // replaying the journal
     }
 
     Ok(())
 }
 
+/// Ask for a user's DB balance on behalf of reconciliation run `request_id`.
+pub async fn send_balance_check(user_id: &str, request_id: &str) {
+    let payload = json!({
+        "userId": user_id,
+        "requestId": request_id
+    })
+    .to_string();
+    let record = FutureRecord::to("balance-request")
+        .key(user_id)
+        .payload(&payload);
+    match send(record).await {
+        Some(Ok(_)) => {}
+        Some(Err((e, _))) => println!("Failed to produce balance check: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Ask for a user's DB holdings of `asset` on behalf of reconciliation run `request_id`.
+pub async fn send_holdings_check(user_id: &str, asset: &str, request_id: &str) {
+    let payload = json!({
+        "userId": user_id,
+        "asset": asset,
+        "requestId": request_id
+    })
+    .to_string();
+    let record = FutureRecord::to("holdings-request")
+        .key(user_id)
+        .payload(&payload);
+    match send(record).await {
+        Some(Ok(_)) => {}
+        Some(Err((e, _))) => println!("Failed to produce holdings check: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Ask db-processor for the live trades and balances a fresh engine starts from.
+pub async fn send_engine_state_request(request_id: &str) {
+    let payload = json!({
+        "requestId": request_id
+    })
+    .to_string();
+    let record = FutureRecord::to("engine-state-request")
+        .key(request_id)
+        .payload(&payload);
+    match send(record).await {
+        Some(Ok(_)) => println!("engine-state-request sent: {}", request_id),
+        Some(Err((e, _))) => println!("Failed to produce engine-state-request: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
 /// Send a trade-create-response event to Kafka.
 pub async fn send_trade_create_response(key: &str, response: &str) {
     let record = FutureRecord::to("trade-create-response")
         .key(key)
         .payload(response);
-    match PRODUCER.send(record, Duration::from_secs(0)).await {
-        Ok(_) => println!("trade-create-response sent for key: {}", key),
-        Err((e, _)) => println!("Failed to produce trade-create-response: {}", e),
+    match send(record).await {
+        Some(Ok(_)) => println!("trade-create-response sent for key: {}", key),
+        Some(Err((e, _))) => println!("Failed to produce trade-create-response: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Send a trade-close-response event to Kafka.
+pub async fn send_trade_close_response(key: &str, response: &str) {
+    let record = FutureRecord::to("trade-close-response")
+        .key(key)
+        .payload(response);
+    match send(record).await {
+        Some(Ok(_)) => println!("trade-close-response sent for key: {}", key),
+        Some(Err((e, _))) => println!("Failed to produce trade-close-response: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Send a margin-mode-response event to Kafka.
+pub async fn send_margin_mode_response(key: &str, response: &str) {
+    let record = FutureRecord::to("margin-mode-response")
+        .key(key)
+        .payload(response);
+    match send(record).await {
+        Some(Ok(_)) => println!("margin-mode-response sent for key: {}", key),
+        Some(Err((e, _))) => println!("Failed to produce margin-mode-response: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Send a position-margin-response event to Kafka.
+pub async fn send_position_margin_response(key: &str, response: &str) {
+    let record = FutureRecord::to("position-margin-response")
+        .key(key)
+        .payload(response);
+    match send(record).await {
+        Some(Ok(_)) => println!("position-margin-response sent for key: {}", key),
+        Some(Err((e, _))) => println!("Failed to produce position-margin-response: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Send a position-leverage-response event to Kafka.
+pub async fn send_position_leverage_response(key: &str, response: &str) {
+    let record = FutureRecord::to("position-leverage-response")
+        .key(key)
+        .payload(response);
+    match send(record).await {
+        Some(Ok(_)) => println!("position-leverage-response sent for key: {}", key),
+        Some(Err((e, _))) => println!("Failed to produce position-leverage-response: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Send an engine-admin-response event to Kafka.
+pub async fn send_admin_response(key: &str, response: &str) {
+    let record = FutureRecord::to("engine-admin-response")
+        .key(key)
+        .payload(response);
+    match send(record).await {
+        Some(Ok(_)) => println!("engine-admin-response sent for key: {}", key),
+        Some(Err((e, _))) => println!("Failed to produce engine-admin-response: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Send a price-query-response event to Kafka.
+pub async fn send_price_query_response(key: &str, response: &str) {
+    let record = FutureRecord::to("price-query-response")
+        .key(key)
+        .payload(response);
+    match send(record).await {
+        Some(Ok(_)) => println!("price-query-response sent for key: {}", key),
+        Some(Err((e, _))) => println!("Failed to produce price-query-response: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Send a candle-query-response event to Kafka.
+pub async fn send_candle_query_response(key: &str, response: &str) {
+    let record = FutureRecord::to("candle-query-response")
+        .key(key)
+        .payload(response);
+    match send(record).await {
+        Some(Ok(_)) => println!("candle-query-response sent for key: {}", key),
+        Some(Err((e, _))) => println!("Failed to produce candle-query-response: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Publish a closed candle to the "candles" topic.
+pub async fn publish_candle(key: &str, candle: &str) {
+    let record = FutureRecord::to("candles").key(key).payload(candle);
+    match send(record).await {
+        Some(Ok(_)) => println!("Candle published: {}", candle),
+        Some(Err((e, _))) => println!("Failed to publish candle: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Publish a trading halt transition to the "trading-halt" topic.
+pub async fn publish_trading_halt(key: &str, event: &str) {
+    let record = FutureRecord::to("trading-halt").key(key).payload(event);
+    match send(record).await {
+        Some(Ok(_)) => println!("Trading halt published: {}", event),
+        Some(Err((e, _))) => println!("Failed to publish trading halt: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Publish a margin call warning to the "margin-call" topic.
+pub async fn publish_margin_call(key: &str, event: &str) {
+    let record = FutureRecord::to("margin-call").key(key).payload(event);
+    match send(record).await {
+        Some(Ok(_)) => println!("Margin call published: {}", event),
+        Some(Err((e, _))) => println!("Failed to publish margin call: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Publish a financing statement to the "financing" topic.
+pub async fn publish_financing_event(key: &str, event: &str) {
+    let record = FutureRecord::to("financing").key(key).payload(event);
+    match send(record).await {
+        Some(Ok(_)) => println!("Financing event published for user: {}", key),
+        Some(Err((e, _))) => println!("Failed to publish financing event: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Publish a market halt/resume event to the "market-status" topic.
+pub async fn publish_market_status(key: &str, event: &str) {
+    let record = FutureRecord::to("market-status").key(key).payload(event);
+    match send(record).await {
+        Some(Ok(_)) => println!("Market status published: {}", event),
+        Some(Err((e, _))) => println!("Failed to publish market status: {}", e),
+        None => {} // replaying the journal
+    }
+}
+
+/// Publish a reconciliation report to the "reconciliation-report" topic.
+pub async fn publish_reconciliation_report(key: &str, report: &str) {
+    let record = FutureRecord::to("reconciliation-report")
+        .key(key)
+        .payload(report);
+    match send(record).await {
+        Some(Ok(_)) => println!("Reconciliation report published: {}", key),
+        Some(Err((e, _))) => println!("Failed to publish reconciliation report: {}", e),
+        None => {} // replaying the journal
     }
 }
 
@@ -85,9 +307,10 @@ pub async fn publish_trade_outcome(msg: &str) -> Result<(), Box<dyn std::error::
         .key(&trade_id)
         .payload(msg);
 
-    match PRODUCER.send(record, Duration::from_secs(0)).await {
-        Ok(_) => println!("Trade outcome published: {}", msg),
-        Err((e, _)) => println!("Failed to publish trade outcome: {}", e),
+    match send(record).await {
+        Some(Ok(_)) => println!("Trade outcome published: {}", msg),
+        Some(Err((e, _))) => println!("Failed to publish trade outcome: {}", e),
+        None => {} // replaying the journal
     }
 
     Ok(())
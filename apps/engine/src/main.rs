@@ -2,13 +2,18 @@ mod kafka;
 mod modules;
 
 use kafka::consumer::{
-    consume_balance_responses, consume_holdings_responses, consume_price_updates,
-    consume_trade_requests,
+    consume_admin_requests, consume_balance_responses, consume_candle_queries,
+    consume_holdings_responses, consume_leverage_requests, consume_margin_mode_requests,
+    consume_position_margin_requests, consume_price_queries, consume_price_updates,
+    consume_trade_close_requests, consume_trade_requests, load_state_from_db,
 };
 use kafka::producer;
-use modules::price_updater::spawn_price_logger;
-use modules::state::EngineState;
-use modules::stop_loss_take_profit::monitor_stop_loss_take_profit;
+use modules::assets::spawn_asset_status_reporter;
+use modules::config::CONFIG;
+use modules::invariants::spawn_invariant_checker;
+use modules::journal::{discard_journal, open_journal, replay_journal, JournalEvent, TimerJob};
+use modules::sequencer::{spawn_sequencer, submit};
+use modules::snapshot::{load_snapshot, snapshot_state, spawn_snapshot_writer};
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
@@ -16,57 +21,216 @@ use tokio::time::Duration;
 
 #[tokio::main]
 async fn main() {
-    let state = Arc::new(Mutex::new(EngineState::new()));
+    // Restore the last snapshot before any consumer can touch state. A snapshot that exists
+    // but can't be read stops startup rather than silently dropping every position.
+    let restored = load_snapshot(&CONFIG.snapshot_path).unwrap_or_else(|e| {
+        panic!(
+            "Failed to load snapshot from {}: {}",
+            CONFIG.snapshot_path, e
+        )
+    });
+    // Without one, rebuild from the database; no consumer starts until that completes
+    let loaded_from_db = restored.is_none();
+    let engine_state = match restored {
+        Some(engine_state) => engine_state,
+        None => {
+            println!(
+                "No snapshot at {}, loading state from the database.",
+                CONFIG.snapshot_path
+            );
+            let engine_state = load_state_from_db().await;
+            discard_journal().unwrap_or_else(|e| {
+                panic!("Failed to discard journal {}: {}", CONFIG.journal_path, e)
+            });
+            engine_state
+        }
+    };
+    let state = Arc::new(Mutex::new(engine_state));
+
+    // Catch up on everything journaled since the snapshot, then keep journaling after it
+    let last_seq = replay_journal(&state)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to replay journal {}: {}", CONFIG.journal_path, e));
+    open_journal(last_seq)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to open journal {}: {}", CONFIG.journal_path, e));
+
+    if loaded_from_db {
+        // Restart from this point rather than loading from the database again
+        snapshot_state(&state).await;
+    }
+
     let (tx, mut rx) = mpsc::channel::<String>(1024);
 
+    // One writer applies every mutating event, in the order consumers and timers submit them
+    let (events, market_view) = spawn_sequencer(state.clone(), tx.clone()).await;
+
     // Spawn Trade Request Consumer (fast jobs)
-    let trade_state = state.clone();
-    let trade_tx = tx.clone();
+    let trade_events = events.clone();
     tokio::spawn(async move {
-        if let Err(e) = consume_trade_requests(trade_state, trade_tx).await {
+        if let Err(e) = consume_trade_requests(trade_events).await {
             eprintln!("Error in Trade Request Consumer: {:?}", e);
         }
     });
 
+    // Spawn Trade Close Consumer
+    let trade_close_events = events.clone();
+    tokio::spawn(async move {
+        if let Err(e) = consume_trade_close_requests(trade_close_events).await {
+            eprintln!("Error in Trade Close Consumer: {:?}", e);
+        }
+    });
+
     // Spawn Price Update Consumer (slow jobs)
-    let price_state = state.clone();
-    let price_tx = tx.clone();
+    let price_events = events.clone();
     tokio::spawn(async move {
-        if let Err(e) = consume_price_updates(price_state, price_tx).await {
+        if let Err(e) = consume_price_updates(price_events).await {
             eprintln!("Error in Price Update Consumer: {:?}", e);
         }
     });
 
     // Spawn Balance Response Consumer
-    let balance_state = state.clone();
-    let balance_tx = tx.clone();
+    let balance_events = events.clone();
     tokio::spawn(async move {
-        if let Err(e) = consume_balance_responses(balance_state, balance_tx).await {
+        if let Err(e) = consume_balance_responses(balance_events).await {
             eprintln!("Error in Balance Response Consumer: {:?}", e);
         }
     });
 
     // Spawn Holdings Response Consumer
-    let holdings_state = state.clone();
-    let holdings_tx = tx.clone();
+    let holdings_events = events.clone();
     tokio::spawn(async move {
-        if let Err(e) = consume_holdings_responses(holdings_state, holdings_tx).await {
+        if let Err(e) = consume_holdings_responses(holdings_events).await {
             eprintln!("Error in Holdings Response Consumer: {:?}", e);
         }
     });
 
-    spawn_price_logger(state.clone());
+    // Spawn Margin Mode Consumer
+    let margin_mode_events = events.clone();
+    tokio::spawn(async move {
+        if let Err(e) = consume_margin_mode_requests(margin_mode_events).await {
+            eprintln!("Error in Margin Mode Consumer: {:?}", e);
+        }
+    });
+
+    // Spawn Position Margin Consumer
+    let position_margin_events = events.clone();
+    tokio::spawn(async move {
+        if let Err(e) = consume_position_margin_requests(position_margin_events).await {
+            eprintln!("Error in Position Margin Consumer: {:?}", e);
+        }
+    });
+
+    // Spawn Leverage Consumer
+    let leverage_events = events.clone();
+    tokio::spawn(async move {
+        if let Err(e) = consume_leverage_requests(leverage_events).await {
+            eprintln!("Error in Leverage Consumer: {:?}", e);
+        }
+    });
+
+    // Spawn Price Query Consumer
+    let price_query_view = market_view.clone();
+    tokio::spawn(async move {
+        if let Err(e) = consume_price_queries(price_query_view).await {
+            eprintln!("Error in Price Query Consumer: {:?}", e);
+        }
+    });
+
+    // Spawn Candle Query Consumer
+    let candle_query_view = market_view.clone();
+    tokio::spawn(async move {
+        if let Err(e) = consume_candle_queries(candle_query_view).await {
+            eprintln!("Error in Candle Query Consumer: {:?}", e);
+        }
+    });
+
+    // Spawn Admin Consumer
+    let admin_events = events.clone();
+    tokio::spawn(async move {
+        if let Err(e) = consume_admin_requests(admin_events).await {
+            eprintln!("Error in Admin Consumer: {:?}", e);
+        }
+    });
+
+    // Periodic reads of the whole state run on the sequencer, between events
+    spawn_asset_status_reporter(events.clone());
+    spawn_snapshot_writer(events.clone());
+    spawn_invariant_checker(events.clone());
 
     // Start stop-loss and take-profit monitoring
-    let stop_loss_state = state.clone();
-    let sl_tx = tx.clone();
+    let stop_loss_events = events.clone();
     tokio::spawn(async move {
         loop {
-            monitor_stop_loss_take_profit(stop_loss_state.clone(), sl_tx.clone()).await;
+            let event = JournalEvent::Timer(TimerJob::StopLossTakeProfit);
+            submit(&stop_loss_events, event).await;
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
     });
 
+    // Close and publish candles whose interval has ended
+    let candle_events = events.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = JournalEvent::Timer(TimerJob::CandleClose);
+            submit(&candle_events, event).await;
+            tokio::time::sleep(Duration::from_millis(1000)).await;
+        }
+    });
+
+    // Halt markets whose price feed has gone quiet
+    let staleness_events = events.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = JournalEvent::Timer(TimerJob::PriceStaleness);
+            submit(&staleness_events, event).await;
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+    });
+
+    // Charge overnight financing once the daily rollover passes
+    let financing_events = events.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = JournalEvent::Timer(TimerJob::Financing);
+            submit(&financing_events, event).await;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+
+    // Close or roll over positions whose trade term has ended
+    let term_events = events.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = JournalEvent::Timer(TimerJob::TermExpiry);
+            submit(&term_events, event).await;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+
+    // Reject orders whose account balance or holdings never arrived
+    let account_load_events = events.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = JournalEvent::Timer(TimerJob::AccountLoadTimeout);
+            submit(&account_load_events, event).await;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+
+    // Compare active users' balances and holdings with the DB
+    let reconciliation_events = events.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(
+                CONFIG.reconciliation_interval_ms.max(1) as u64,
+            ))
+            .await;
+            let event = JournalEvent::Timer(TimerJob::Reconciliation);
+            submit(&reconciliation_events, event).await;
+        }
+    });
+
     tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
             if let Err(e) = producer::publish_trade_outcome(&msg).await {
@@ -79,5 +243,6 @@ async fn main() {
     tokio::signal::ctrl_c()
         .await
         .expect("Failed to listen for ctrl_c");
+    snapshot_state(&state).await;
     println!("Shutting down engine.");
 }
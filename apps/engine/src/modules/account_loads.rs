@@ -0,0 +1,105 @@
+use crate::kafka::producer;
+use crate::modules::config::CONFIG;
+use crate::modules::processor::fail_trade_request;
+use crate::modules::state::{EngineState, SharedEngineState};
+use crate::modules::types::{AccountLoadStatus, CreateTradeRequest};
+use std::collections::VecDeque;
+
+/// Hold `req` back while the user's account is loading or lacks what the order needs,
+/// requesting anything not already in flight. Returns the request when it can go ahead now.
+pub async fn queue_until_loaded(
+    engine_state: &mut EngineState,
+    req: CreateTradeRequest,
+    now: i64,
+) -> Option<CreateTradeRequest> {
+    let needs_balance = !engine_state.balances.contains_key(&req.user_id);
+    let needs_holdings = !engine_state
+        .holdings
+        .contains_key(&(req.user_id.clone(), req.asset.clone()));
+    let loading = engine_state
+        .account_loads
+        .get(&req.user_id)
+        .is_some_and(|load| load.status == AccountLoadStatus::Loading);
+    // Orders behind a load in progress wait their turn even if their data is already here
+    if !needs_balance && !needs_holdings && !loading {
+        return Some(req);
+    }
+
+    let load = engine_state
+        .account_loads
+        .entry(req.user_id.clone())
+        .or_default();
+    if load.status != AccountLoadStatus::Loading {
+        load.status = AccountLoadStatus::Loading;
+        load.started_at = now;
+    }
+    if needs_balance && !load.balance_requested {
+        println!(
+            "No balance found for user: {}. Requesting balance...",
+            req.user_id
+        );
+        if let Err(e) = producer::send_balance_request(&req.user_id).await {
+            eprintln!("Failed to send balance request: {:?}", e);
+        }
+        load.balance_requested = true;
+    }
+    if needs_holdings && !load.holdings_requested.contains(&req.asset) {
+        if let Err(e) = producer::send_holdings_request(&req.user_id, &req.asset).await {
+            eprintln!("Failed to send holdings request: {:?}", e);
+        }
+        load.holdings_requested.insert(req.asset.clone());
+    }
+    load.queued.push_back(req);
+    None
+}
+
+/// Once nothing is in flight for `user_id`, mark the account ready and hand back its queued
+/// orders, oldest first.
+pub fn take_ready_orders(
+    engine_state: &mut EngineState,
+    user_id: &str,
+) -> VecDeque<CreateTradeRequest> {
+    match engine_state.account_loads.get_mut(user_id) {
+        Some(load) if !load.awaiting_answers() => {
+            load.status = AccountLoadStatus::Ready;
+            std::mem::take(&mut load.queued)
+        }
+        _ => VecDeque::new(),
+    }
+}
+
+/// Reject the queued orders of accounts still loading past the configured deadline. The next
+/// order from such a user starts a fresh load. Returns whether any load timed out.
+pub async fn process_account_load_timeouts(state: SharedEngineState, now: i64) -> bool {
+    let mut engine_state = state.lock().await;
+    let mut any_timed_out = false;
+    let mut timed_out = Vec::new();
+    for (user_id, load) in engine_state.account_loads.iter_mut() {
+        if load.status != AccountLoadStatus::Loading
+            || now - load.started_at < CONFIG.account_load_timeout_ms
+        {
+            continue;
+        }
+        eprintln!(
+            "Account load for user {} timed out; rejecting {} queued orders",
+            user_id,
+            load.queued.len()
+        );
+        any_timed_out = true;
+        load.status = AccountLoadStatus::Failed;
+        load.balance_requested = false;
+        load.holdings_requested.clear();
+        timed_out.extend(load.queued.drain(..));
+    }
+
+    for req in timed_out {
+        fail_trade_request(
+            &mut engine_state,
+            &req,
+            "timeout",
+            "Timed out loading account balance and holdings",
+        )
+        .await;
+    }
+    any_timed_out
+}
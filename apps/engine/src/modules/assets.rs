@@ -0,0 +1,419 @@
+use crate::kafka::producer;
+use crate::modules::config::CONFIG;
+use crate::modules::halts::{apply_halt_command, publish_halt_event};
+use crate::modules::market_status::publish_market_status;
+use crate::modules::price_store::PRICE_STORE;
+use crate::modules::sequencer::{submit_task, EventSender, SequencerTask};
+use crate::modules::settlement::{cancel_resting_order, close_trade_at_price};
+use crate::modules::state::{EngineState, SharedEngineState};
+use crate::modules::types::{
+    AdminCommand, AdminRequest, AssetInfo, AssetStatus, MarketStatusEvent, TradeOutcome,
+};
+use tokio::time::{sleep, Duration};
+
+/// Apply an admin command to the asset registry. Returns the asset's new status with the outcomes
+/// of any orders and positions it settled, or why the command was refused.
+pub fn apply_admin_command(
+    engine_state: &mut EngineState,
+    command: &AdminCommand,
+    timestamp: i64,
+) -> Result<(AssetStatus, Vec<TradeOutcome>), &'static str> {
+    match command {
+        AdminCommand::Add {
+            asset,
+            max_leverage,
+        } => {
+            if max_leverage.is_some_and(|leverage| leverage < 1 || leverage > CONFIG.max_leverage) {
+                return Err("Max leverage outside engine limits");
+            }
+            if engine_state.asset_status(asset) == Some(AssetStatus::Delisted) {
+                return Err("Asset was delisted");
+            }
+            let info = engine_state
+                .assets
+                .entry(asset.clone())
+                .or_insert(AssetInfo {
+                    status: AssetStatus::Trading,
+                    max_leverage: None,
+                });
+            info.max_leverage = *max_leverage;
+            Ok((info.status, Vec::new()))
+        }
+        AdminCommand::Suspend { asset } => set_status(
+            engine_state,
+            asset,
+            AssetStatus::Trading,
+            AssetStatus::Suspended,
+        )
+        .map(|status| (status, Vec::new())),
+        AdminCommand::Resume { asset } => set_status(
+            engine_state,
+            asset,
+            AssetStatus::Suspended,
+            AssetStatus::Trading,
+        )
+        .map(|status| (status, Vec::new())),
+        AdminCommand::Halt { .. } | AdminCommand::LiftHalt { .. } => Err("Not an asset command"),
+        AdminCommand::Delist {
+            asset,
+            settlement_price,
+        } => match engine_state.asset_status(asset) {
+            None => Err("Unknown asset"),
+            Some(AssetStatus::Delisted) => Err("Asset already delisted"),
+            Some(_) if *settlement_price <= 0 => Err("Settlement price must be positive"),
+            Some(_) => {
+                let outcomes = delist_asset(engine_state, asset, *settlement_price, timestamp);
+                Ok((AssetStatus::Delisted, outcomes))
+            }
+        },
+    }
+}
+
+fn set_status(
+    engine_state: &mut EngineState,
+    asset: &str,
+    from: AssetStatus,
+    to: AssetStatus,
+) -> Result<AssetStatus, &'static str> {
+    match engine_state.assets.get_mut(asset) {
+        None => Err("Unknown asset"),
+        Some(info) if info.status != from => Err("Asset not in the required status"),
+        Some(info) => {
+            info.status = to;
+            Ok(to)
+        }
+    }
+}
+
+/// Take an asset out of trading for good: cancel its resting orders with margin refunds, close
+/// every open position at the settlement price and mark the asset delisted.
+fn delist_asset(
+    engine_state: &mut EngineState,
+    asset: &str,
+    settlement_price: i64,
+    timestamp: i64,
+) -> Vec<TradeOutcome> {
+    let mut outcomes = Vec::new();
+    if let Some(book) = engine_state.order_books.remove(asset) {
+        for order in book
+            .buy
+            .into_values()
+            .chain(book.sell.into_values())
+            .flatten()
+        {
+            let outcome = cancel_resting_order(engine_state, &order, "delisted", timestamp);
+            // The remainder of a partial fill shares its id with the position closed below;
+            // its margin is refunded, but only the close is published for that id
+            if !engine_state.open_trades.contains_key(&order.id) {
+                outcomes.push(outcome);
+            }
+        }
+    }
+
+    let mut trade_ids: Vec<String> = engine_state
+        .open_trades
+        .iter()
+        .filter(|(_, trade)| trade.asset == asset)
+        .map(|(id, _)| id.clone())
+        .collect();
+    trade_ids.sort();
+    for trade_id in trade_ids {
+        let cap_loss_at_margin =
+            engine_state.caps_loss_at_margin(&engine_state.open_trades[&trade_id].user_id);
+        outcomes.extend(close_trade_at_price(
+            engine_state,
+            &trade_id,
+            settlement_price,
+            "closed",
+            Some("delisted"),
+            cap_loss_at_margin,
+            timestamp,
+        ));
+    }
+
+    if let Some(info) = engine_state.assets.get_mut(asset) {
+        info.status = AssetStatus::Delisted;
+    }
+    // A delisted asset's feed may stop at any time; don't report it as stale
+    engine_state.price_updated_at.remove(asset);
+    engine_state.stale_markets.remove(asset);
+    println!(
+        "Delisted {} at {}: {} orders and positions settled",
+        asset,
+        settlement_price,
+        outcomes.len()
+    );
+    outcomes
+}
+
+/// Asset or user a command applies to; "global" for engine-wide halts.
+fn command_target(command: &AdminCommand) -> &str {
+    match command {
+        AdminCommand::Add { asset, .. }
+        | AdminCommand::Suspend { asset }
+        | AdminCommand::Resume { asset }
+        | AdminCommand::Delist { asset, .. } => asset,
+        AdminCommand::Halt { target, .. } | AdminCommand::LiftHalt { target, .. } => {
+            target.as_deref().unwrap_or("global")
+        }
+    }
+}
+
+/// Handle a request from the "engine-admin" topic: update the asset registry or the trading
+/// halts, answer the caller and announce the transition on "market-status" / "trading-halt".
+pub async fn process_admin_request(
+    state: SharedEngineState,
+    req: AdminRequest,
+    tx: tokio::sync::mpsc::Sender<String>,
+) {
+    let mut engine_state = state.lock().await;
+    let target = command_target(&req.command).to_string();
+
+    let mut response_json = match req.command {
+        AdminCommand::Halt { .. } | AdminCommand::LiftHalt { .. } => {
+            match apply_halt_command(&mut engine_state, &req.command, req.timestamp) {
+                Ok(event) => {
+                    println!("Admin command {:?} applied: {}", req.command, event.status);
+                    publish_halt_event(&event).await;
+                    serde_json::json!({
+                        "target": target,
+                        "status": "accepted",
+                        "halt": event
+                    })
+                }
+                Err(reason) => {
+                    println!("Rejected admin command {:?}: {}", req.command, reason);
+                    serde_json::json!({
+                        "target": target,
+                        "status": "rejected",
+                        "reason": reason
+                    })
+                }
+            }
+        }
+        _ => match apply_admin_command(&mut engine_state, &req.command, req.timestamp) {
+            Ok((status, outcomes)) => {
+                for outcome in outcomes {
+                    if let Ok(json_string) = serde_json::to_string(&outcome) {
+                        let _ = tx.send(json_string).await;
+                    }
+                }
+                println!(
+                    "Admin command {:?} applied. {} is {:?}",
+                    req.command, target, status
+                );
+                let event = MarketStatusEvent {
+                    asset: target.clone(),
+                    status: status.as_str().to_string(),
+                    reason: "admin".to_string(),
+                    last_price_at: engine_state.price_updated_at.get(&target).copied(),
+                    timestamp: req.timestamp,
+                };
+                publish_market_status(&event).await;
+                serde_json::json!({
+                    "asset": target,
+                    "status": "accepted",
+                    "assetStatus": status,
+                    "maxLeverage": engine_state.max_leverage(&target)
+                })
+            }
+            Err(reason) => {
+                println!("Rejected admin command {:?}: {}", req.command, reason);
+                serde_json::json!({
+                    "asset": target,
+                    "status": "rejected",
+                    "reason": reason
+                })
+            }
+        },
+    };
+    if let Some(ref corr_id) = req.correlation_id {
+        response_json["correlationId"] = serde_json::json!(corr_id);
+    }
+    let response = response_json.to_string();
+    producer::send_admin_response(&target, &response).await;
+}
+
+/// Log the status, quote and leverage cap of every registered asset.
+pub async fn report_asset_status(state: &SharedEngineState) {
+    // Quotes come from the price store; the state lock is only held for the registry
+    let prices = PRICE_STORE.latest();
+    let engine_state = state.lock().await;
+    let now = chrono::Utc::now().timestamp_millis();
+    let mut assets: Vec<&String> = engine_state.assets.keys().collect();
+    assets.sort();
+    let report: Vec<String> = assets
+        .into_iter()
+        .map(|asset| {
+            let info = &engine_state.assets[asset];
+            let point = prices.get(asset);
+            let quote = point
+                .map(|point| format!("{}/{}", point.quote.bid, point.quote.ask))
+                .unwrap_or_else(|| "-".to_string());
+            let age = point
+                .map(|point| format!("{}ms", now - point.updated_at))
+                .unwrap_or_else(|| "never".to_string());
+            let halted = if engine_state.is_market_halted(asset) {
+                " halted"
+            } else {
+                ""
+            };
+            format!(
+                "{}: {:?}{} {} ({}) x{}",
+                asset,
+                info.status,
+                halted,
+                quote,
+                age,
+                engine_state.max_leverage(asset)
+            )
+        })
+        .collect();
+    println!("Assets => {}", report.join(" | "));
+}
+
+/// Call this once at startup to have the sequencer report every asset's status periodically.
+pub fn spawn_asset_status_reporter(events: EventSender) {
+    tokio::spawn(async move {
+        loop {
+            submit_task(&events, SequencerTask::AssetReport).await;
+            sleep(Duration::from_secs(10)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::invariants::check_funds;
+    use crate::modules::state::OrderBook;
+    use crate::modules::types::{MarginMode, Order, OrderStatus, OrderType, Side, Trade};
+
+    fn resting(id: &str, user_id: &str, side: Side, price: i64, margin: i64) -> Order {
+        Order {
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            asset: "BTC".to_string(),
+            side,
+            order_type: OrderType::Limit,
+            price: Some(price),
+            quantity: 1,
+            filled: 0,
+            status: OrderStatus::Open,
+            margin,
+            leverage: 10,
+            stop_loss_percent: None,
+            take_profit_percent: None,
+            created_at: 0,
+            expiry: None,
+            term: None,
+        }
+    }
+
+    /// A long position with its partial fill's remainder still resting, plus another user's order.
+    fn listed_state() -> EngineState {
+        let mut engine_state = EngineState::new();
+        engine_state.assets.insert(
+            "BTC".to_string(),
+            AssetInfo {
+                status: AssetStatus::Trading,
+                max_leverage: None,
+            },
+        );
+        engine_state.balances.insert("user".to_string(), 1_000);
+        engine_state.balances.insert("other".to_string(), 0);
+        let trade = Trade {
+            id: "order".to_string(),
+            user_id: "user".to_string(),
+            asset: "BTC".to_string(),
+            side: Side::Buy,
+            margin: 100,
+            leverage: 10,
+            quantity: 1,
+            entry_price: Some(100),
+            close_price: None,
+            pnl: None,
+            status: None,
+            created_at: Some(0),
+            closed_at: None,
+            take_profit_percent: None,
+            stop_loss_percent: None,
+            price: Some(100),
+            term: None,
+            order_type: Some(OrderType::Limit),
+            limit_price: Some(100),
+        };
+        engine_state.open_trades.insert(trade.id.clone(), trade);
+        engine_state.set_locked_margin("order", 100);
+
+        let mut book = OrderBook::new();
+        book.buy
+            .entry(100)
+            .or_default()
+            .push_back(resting("order", "user", Side::Buy, 100, 50));
+        book.sell.entry(120).or_default().push_back(resting(
+            "resting",
+            "other",
+            Side::Sell,
+            120,
+            30,
+        ));
+        engine_state.order_books.insert("BTC".to_string(), book);
+        engine_state.ledger.deposits = 1_180;
+        engine_state
+    }
+
+    fn delist(settlement_price: i64) -> AdminCommand {
+        AdminCommand::Delist {
+            asset: "BTC".to_string(),
+            settlement_price,
+        }
+    }
+
+    #[test]
+    fn delisting_cancels_orders_and_settles_positions() {
+        let mut engine_state = listed_state();
+        let (status, outcomes) = apply_admin_command(&mut engine_state, &delist(80), 1).unwrap();
+        assert_eq!(status, AssetStatus::Delisted);
+
+        // The remainder sharing the position's id is refunded but only its close is published
+        let reported: Vec<(&str, Option<&str>)> = outcomes
+            .iter()
+            .map(|outcome| (outcome.trade_id.as_str(), outcome.status.as_deref()))
+            .collect();
+        assert_eq!(
+            reported,
+            vec![("resting", Some("cancelled")), ("order", Some("closed"))]
+        );
+        assert!(!engine_state.order_books.contains_key("BTC"));
+        assert!(engine_state.open_trades.is_empty());
+        assert_eq!(engine_state.balances["other"], 30);
+        // Isolated: the 200 loss at 80 is capped at the 100 margin
+        assert_eq!(engine_state.balances["user"], 1_050);
+        assert_eq!(check_funds(&engine_state).discrepancy(), 0);
+    }
+
+    #[test]
+    fn delisting_settles_cross_losses_in_full() {
+        let mut engine_state = listed_state();
+        engine_state
+            .margin_modes
+            .insert("user".to_string(), MarginMode::Cross);
+        apply_admin_command(&mut engine_state, &delist(80), 1).unwrap();
+        assert_eq!(engine_state.balances["user"], 950);
+        assert_eq!(check_funds(&engine_state).discrepancy(), 0);
+    }
+
+    #[test]
+    fn delisted_assets_stay_delisted() {
+        let mut engine_state = listed_state();
+        assert!(apply_admin_command(&mut engine_state, &delist(0), 1).is_err());
+        apply_admin_command(&mut engine_state, &delist(80), 1).unwrap();
+        assert!(apply_admin_command(&mut engine_state, &delist(80), 2).is_err());
+        let relist = AdminCommand::Add {
+            asset: "BTC".to_string(),
+            max_leverage: None,
+        };
+        assert!(apply_admin_command(&mut engine_state, &relist, 3).is_err());
+    }
+}
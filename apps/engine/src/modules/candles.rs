@@ -0,0 +1,171 @@
+use crate::kafka::producer;
+use crate::modules::config::CONFIG;
+use crate::modules::sequencer::MarketView;
+use crate::modules::state::{EngineState, SharedEngineState};
+use crate::modules::types::{Candle, CandleInterval, CandleQueryRequest};
+use std::sync::Arc;
+
+/// Close the asset's candle for `interval` if `now` falls past its end, and return the
+/// candle in progress for `now`'s bucket, opening it at `price` if needed.
+fn roll_candle<'a>(
+    engine_state: &'a mut EngineState,
+    asset: &str,
+    interval: CandleInterval,
+    price: i64,
+    now: i64,
+    closed: &mut Vec<Candle>,
+) -> &'a mut Candle {
+    let open_time = now - now.rem_euclid(interval.duration_ms());
+    let series = engine_state
+        .open_candles
+        .entry(asset.to_string())
+        .or_default();
+    if let Some(candle) = series.get(&interval) {
+        if candle.open_time != open_time {
+            if let Some(candle) = series.remove(&interval) {
+                closed.push(candle);
+            }
+        }
+    }
+    series.entry(interval).or_insert_with(|| Candle {
+        asset: asset.to_string(),
+        interval,
+        open_time,
+        close_time: open_time + interval.duration_ms(),
+        open: price,
+        high: price,
+        low: price,
+        close: price,
+        volume: 0,
+        trades: 0,
+    })
+}
+
+/// Keep closed candles for snapshot queries, bounded per asset and interval.
+fn store_closed(engine_state: &mut EngineState, closed: &[Candle]) {
+    for candle in closed {
+        let history = Arc::make_mut(
+            engine_state
+                .closed_candles
+                .entry(candle.asset.clone())
+                .or_default()
+                .entry(candle.interval)
+                .or_default(),
+        );
+        history.push_back(candle.clone());
+        while history.len() > CONFIG.candle_history_len {
+            history.pop_front();
+        }
+    }
+}
+
+/// Fold an aggregated price into every interval's candle, widening it to `(low, high)`, the
+/// range the aggregated price spanned since the last one. Returns candles closed by it.
+pub fn record_tick(
+    engine_state: &mut EngineState,
+    asset: &str,
+    price: i64,
+    (low, high): (i64, i64),
+    now: i64,
+) -> Vec<Candle> {
+    let mut closed = Vec::new();
+    for interval in CandleInterval::ALL {
+        let candle = roll_candle(engine_state, asset, interval, price, now, &mut closed);
+        candle.high = candle.high.max(price).max(high);
+        candle.low = candle.low.min(price).min(low);
+        candle.close = price;
+    }
+    store_closed(engine_state, &closed);
+    closed
+}
+
+/// Add an executed fill's quantity to every interval's candle. Prices come from ticks only;
+/// the fill price just opens the candle when no tick has arrived for the bucket yet.
+pub fn record_fill(
+    engine_state: &mut EngineState,
+    asset: &str,
+    price: i64,
+    quantity: i64,
+    now: i64,
+) -> Vec<Candle> {
+    let open_price = engine_state.prices.get(asset).copied().unwrap_or(price);
+    let mut closed = Vec::new();
+    for interval in CandleInterval::ALL {
+        let candle = roll_candle(engine_state, asset, interval, open_price, now, &mut closed);
+        candle.volume += quantity;
+        candle.trades += 1;
+    }
+    store_closed(engine_state, &closed);
+    closed
+}
+
+/// Close every candle whose interval has ended, so quiet markets still publish on time.
+pub fn close_elapsed_candles(engine_state: &mut EngineState, now: i64) -> Vec<Candle> {
+    let mut closed = Vec::new();
+    for series in engine_state.open_candles.values_mut() {
+        series.retain(|_, candle| {
+            if candle.close_time <= now {
+                closed.push(candle.clone());
+                false
+            } else {
+                true
+            }
+        });
+    }
+    closed.sort_by(|a, b| {
+        (a.close_time, &a.asset, a.interval.duration_ms()).cmp(&(
+            b.close_time,
+            &b.asset,
+            b.interval.duration_ms(),
+        ))
+    });
+    store_closed(engine_state, &closed);
+    closed
+}
+
+pub async fn publish_candles(candles: &[Candle]) {
+    for candle in candles {
+        if let Ok(json_string) = serde_json::to_string(candle) {
+            producer::publish_candle(&candle.asset, &json_string).await;
+        }
+    }
+}
+
+/// Run the candle close job against shared state and publish the closed candles.
+pub async fn process_candle_closes(state: SharedEngineState, now: i64) -> bool {
+    let mut engine_state = state.lock().await;
+    let closed = close_elapsed_candles(&mut engine_state, now);
+    publish_candles(&closed).await;
+    !closed.is_empty()
+}
+
+/// Answer a chart snapshot query with the most recent closed candles and the one in progress.
+pub async fn process_candle_query(market: &MarketView, req: CandleQueryRequest) {
+    let closed: Vec<&Candle> = market
+        .closed_candles
+        .get(&req.asset)
+        .and_then(|series| series.get(&req.interval))
+        .map(|history| {
+            let skip = history
+                .len()
+                .saturating_sub(req.limit.unwrap_or(history.len()));
+            history.iter().skip(skip).collect()
+        })
+        .unwrap_or_default();
+    let current = market
+        .open_candles
+        .get(&req.asset)
+        .and_then(|series| series.get(&req.interval));
+
+    let mut response_json = serde_json::json!({
+        "asset": req.asset,
+        "interval": req.interval,
+        "candles": closed,
+        "current": current
+    });
+    if let Some(ref corr_id) = req.correlation_id {
+        response_json["correlationId"] = serde_json::json!(corr_id);
+    }
+    let response = response_json.to_string();
+    producer::send_candle_query_response(&req.asset, &response).await;
+}
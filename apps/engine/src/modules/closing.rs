@@ -0,0 +1,141 @@
+use crate::kafka::producer;
+use crate::modules::halts::{halt_rejection, HaltAction};
+use crate::modules::settlement::{cancel_resting_order, close_trade_at_price};
+use crate::modules::state::{EngineState, SharedEngineState};
+use crate::modules::types::{CloseTradeRequest, Order};
+
+/// Take the user's resting order `order_id` out of its order book, if there is one.
+fn remove_resting_order(
+    engine_state: &mut EngineState,
+    user_id: &str,
+    order_id: &str,
+) -> Option<Order> {
+    for book in engine_state.order_books.values_mut() {
+        for side in [&mut book.buy, &mut book.sell] {
+            let mut found = None;
+            for (price, orders) in side.iter_mut() {
+                if let Some(index) = orders
+                    .iter()
+                    .position(|order| order.id == order_id && order.user_id == user_id)
+                {
+                    found = orders.remove(index).map(|order| (*price, order));
+                    break;
+                }
+            }
+            if let Some((price, order)) = found {
+                if side.get(&price).is_some_and(|orders| orders.is_empty()) {
+                    side.remove(&price);
+                }
+                return Some(order);
+            }
+        }
+    }
+    None
+}
+
+/// Handle a request from the "trade-close-request" topic: cancel the unfilled remainder of the
+/// order if it is resting, and close its open position at the current exit price. Nothing is
+/// changed when any part is blocked by a trading halt or a stale price feed.
+pub async fn process_trade_close(
+    state: SharedEngineState,
+    req: CloseTradeRequest,
+    tx: tokio::sync::mpsc::Sender<String>,
+) {
+    let mut engine_state = state.lock().await;
+
+    let resting_asset = engine_state.order_books.iter().find_map(|(asset, book)| {
+        book.buy
+            .values()
+            .chain(book.sell.values())
+            .flatten()
+            .any(|order| order.id == req.order_id && order.user_id == req.user_id)
+            .then(|| asset.clone())
+    });
+    let position = engine_state
+        .open_trades
+        .get(&req.order_id)
+        .filter(|trade| trade.user_id == req.user_id)
+        .map(|trade| (trade.asset.clone(), engine_state.exit_price(trade)));
+
+    let rejection = if resting_asset.is_none() && position.is_none() {
+        Some("Order not found".to_string())
+    } else if let Some(reason) = resting_asset
+        .as_ref()
+        .and_then(|asset| halt_rejection(&engine_state, &req.user_id, asset, HaltAction::Cancel))
+    {
+        Some(reason)
+    } else if let Some((asset, price)) = &position {
+        if let Some(reason) = halt_rejection(&engine_state, &req.user_id, asset, HaltAction::Close)
+        {
+            Some(reason)
+        } else if engine_state.is_market_halted(asset) {
+            Some(format!("Trading halted for {}: stale price", asset))
+        } else if price.is_none() {
+            Some("No price available for asset".to_string())
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+
+    if let Some(reason) = rejection {
+        println!(
+            "Rejected close for order {} by user {}: {}",
+            req.order_id, req.user_id, reason
+        );
+        let mut response_json = serde_json::json!({
+            "userId": req.user_id,
+            "orderId": req.order_id,
+            "status": "rejected",
+            "reason": reason
+        });
+        if let Some(ref corr_id) = req.correlation_id {
+            response_json["correlationId"] = serde_json::json!(corr_id);
+        }
+        let response = response_json.to_string();
+        producer::send_trade_close_response(&req.order_id, &response).await;
+        return;
+    }
+
+    let mut outcomes = Vec::new();
+    if let Some(order) = remove_resting_order(&mut engine_state, &req.user_id, &req.order_id) {
+        let outcome =
+            cancel_resting_order(&mut engine_state, &order, "user_cancelled", req.timestamp);
+        // A partial-fill remainder shares its id with the position closed below
+        if position.is_none() {
+            outcomes.push(outcome);
+        }
+    }
+    if let Some((_, Some(price))) = position {
+        let reason = req.reason.as_deref().unwrap_or("user_closed");
+        let cap_loss_at_margin = engine_state.caps_loss_at_margin(&req.user_id);
+        outcomes.extend(close_trade_at_price(
+            &mut engine_state,
+            &req.order_id,
+            price,
+            "closed",
+            Some(reason),
+            cap_loss_at_margin,
+            req.timestamp,
+        ));
+    }
+
+    let mut response_json = serde_json::json!({
+        "userId": req.user_id,
+        "orderId": req.order_id,
+        "status": "accepted",
+        "updatedBalance": engine_state.balances.get(&req.user_id).copied()
+    });
+    if let Some(ref corr_id) = req.correlation_id {
+        response_json["correlationId"] = serde_json::json!(corr_id);
+    }
+    let response = response_json.to_string();
+    producer::send_trade_close_response(&req.order_id, &response).await;
+
+    for outcome in outcomes {
+        if let Ok(json_string) = serde_json::to_string(&outcome) {
+            let _ = tx.send(json_string).await;
+        }
+    }
+}
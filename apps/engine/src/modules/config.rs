@@ -0,0 +1,167 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+
+/// Engine settings, read once from the environment with defaults for local runs.
+pub struct EngineConfig {
+    pub max_leverage: i64, // highest leverage a position may carry; initial margin = notional / max_leverage
+    pub margin_call_levels: Vec<i64>, // margin level thresholds (% of maintenance), highest first
+    pub margin_call_hysteresis: i64, // points above a threshold before its warning clears
+    pub financing_rates: HashMap<String, FinancingRate>, // asset -> daily financing rates
+    pub default_financing_rate: FinancingRate, // for assets without an explicit rate
+    pub financing_rollover_minute: i64, // minutes after 00:00 UTC when financing is charged
+    pub term_rollover_fee_bps: i64, // fee (bps of notional) to roll a position into its next term
+    pub price_max_age_ms: i64, // feed silence after which an asset's market is halted
+    pub price_deviation_bands: HashMap<String, i64>, // asset -> max move (bps) from the last accepted price
+    pub default_price_deviation_bps: i64,            // for assets without an explicit band
+    pub price_confirmation_ticks: i64, // consecutive ticks needed to accept a move outside the band
+    pub price_quorum: usize,           // fresh sources needed before an asset's price is updated
+    pub price_source_max_age_ms: i64,  // quotes older than this are left out of the median
+    pub price_history_ms: i64,         // how far back aggregated prices are kept for queries
+    pub price_history_spacing_ms: i64, // least time between kept prices; newer ones replace the latest
+    pub default_assets: Vec<String>,   // assets registered as trading at startup
+    pub snapshot_path: String,         // where engine state is snapshotted and restored from
+    pub journal_path: String,          // write-ahead journal of inputs since the last snapshot
+    pub journal_batch_max: usize,      // most queued events journaled under one fsync
+    pub snapshot_interval_ms: i64,     // time between periodic snapshots
+    pub invariant_check_interval_ms: i64, // time between funds conservation checks
+    pub invariant_check_every_event: bool, // also check funds after every journal entry (debug builds by default)
+    pub reconciliation_interval_ms: i64,   // time between balance/holdings checks against the DB
+    pub reconciliation_auto_correct: Vec<String>, // kinds ("balance", "holdings") the engine may correct
+    pub reconciliation_max_correction: i64,       // largest difference corrected automatically
+    pub sequencer_queue_len: usize, // events waiting for the sequencer before submitters wait too
+    pub dedup_window_ms: i64,       // how long a trade request's correlation id is remembered
+    pub dedup_max_entries: usize,   // most correlation ids remembered; oldest are forgotten first
+    pub account_load_timeout_ms: i64, // wait for a user's balance/holdings before rejecting queued orders
+    pub state_load_timeout_ms: i64,   // wait for the DB's state-load answer before asking again
+    pub candle_history_len: usize,    // closed candles kept per asset and interval for snapshots
+}
+
+/// Daily financing in basis points of notional: positive rates charge, negative rates credit.
+#[derive(Debug, Clone, Copy)]
+pub struct FinancingRate {
+    pub long_bps: i64,
+    pub short_bps: i64,
+}
+
+impl FromStr for FinancingRate {
+    type Err = std::num::ParseIntError;
+
+    /// Parses "long_bps:short_bps", e.g. "3:-1".
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (long, short) = raw.split_once(':').unwrap_or((raw, raw));
+        Ok(Self {
+            long_bps: long.trim().parse()?,
+            short_bps: short.trim().parse()?,
+        })
+    }
+}
+
+pub static CONFIG: Lazy<EngineConfig> = Lazy::new(|| {
+    let mut margin_call_levels = env_list("ENGINE_MARGIN_CALL_LEVELS", vec![150, 120]);
+    margin_call_levels.sort_unstable_by(|a, b| b.cmp(a));
+    EngineConfig {
+        max_leverage: env_or("ENGINE_MAX_LEVERAGE", 100),
+        margin_call_levels,
+        margin_call_hysteresis: env_or("ENGINE_MARGIN_CALL_HYSTERESIS", 10),
+        // e.g. "BTC_USDC=3:-1,ETH_USDC=4:-2"
+        financing_rates: env_list::<String>("ENGINE_FINANCING_RATES", Vec::new())
+            .iter()
+            .filter_map(|entry| {
+                let (asset, rate) = entry.split_once('=')?;
+                Some((asset.trim().to_string(), rate.parse().ok()?))
+            })
+            .collect(),
+        default_financing_rate: env_or(
+            "ENGINE_FINANCING_DEFAULT_RATE",
+            FinancingRate {
+                long_bps: 0,
+                short_bps: 0,
+            },
+        ),
+        financing_rollover_minute: env::var("ENGINE_FINANCING_ROLLOVER")
+            .ok()
+            .and_then(|raw| {
+                let (hour, minute) = raw.split_once(':')?;
+                Some(hour.trim().parse::<i64>().ok()? * 60 + minute.trim().parse::<i64>().ok()?)
+            })
+            .unwrap_or(22 * 60),
+        term_rollover_fee_bps: env_or("ENGINE_TERM_ROLLOVER_FEE_BPS", 10),
+        price_max_age_ms: env_or("ENGINE_PRICE_MAX_AGE_MS", 5_000),
+        // e.g. "BTC_USDC=500,DOGE_USDC=1500"
+        price_deviation_bands: env_list::<String>("ENGINE_PRICE_DEVIATION_BANDS", Vec::new())
+            .iter()
+            .filter_map(|entry| {
+                let (asset, bps) = entry.split_once('=')?;
+                Some((asset.trim().to_string(), bps.trim().parse().ok()?))
+            })
+            .collect(),
+        default_price_deviation_bps: env_or("ENGINE_PRICE_DEVIATION_BPS", 1_000),
+        price_confirmation_ticks: env_or("ENGINE_PRICE_CONFIRMATION_TICKS", 3),
+        price_quorum: env_or("ENGINE_PRICE_QUORUM", 1),
+        price_source_max_age_ms: env_or("ENGINE_PRICE_SOURCE_MAX_AGE_MS", 3_000),
+        price_history_ms: env_or("ENGINE_PRICE_HISTORY_MS", 86_400_000),
+        price_history_spacing_ms: env_or("ENGINE_PRICE_HISTORY_SPACING_MS", 10_000),
+        candle_history_len: env_or("ENGINE_CANDLE_HISTORY_LEN", 500),
+        snapshot_path: env_or("ENGINE_SNAPSHOT_PATH", "engine-snapshot.json".to_string()),
+        journal_path: env_or("ENGINE_JOURNAL_PATH", "engine-journal.jsonl".to_string()),
+        journal_batch_max: env_or("ENGINE_JOURNAL_BATCH_MAX", 256),
+        snapshot_interval_ms: env_or("ENGINE_SNAPSHOT_INTERVAL_MS", 5_000),
+        reconciliation_interval_ms: env_or("ENGINE_RECONCILIATION_INTERVAL_MS", 60_000),
+        reconciliation_auto_correct: env_list("ENGINE_RECONCILIATION_AUTO_CORRECT", Vec::new()),
+        reconciliation_max_correction: env_or("ENGINE_RECONCILIATION_MAX_CORRECTION", i64::MAX),
+        invariant_check_interval_ms: env_or("ENGINE_INVARIANT_CHECK_INTERVAL_MS", 10_000),
+        invariant_check_every_event: env_or(
+            "ENGINE_INVARIANT_CHECK_EVERY_EVENT",
+            cfg!(debug_assertions),
+        ),
+        sequencer_queue_len: env_or("ENGINE_SEQUENCER_QUEUE_LEN", 10_000),
+        dedup_window_ms: env_or("ENGINE_DEDUP_WINDOW_MS", 3_600_000),
+        dedup_max_entries: env_or("ENGINE_DEDUP_MAX_ENTRIES", 100_000),
+        account_load_timeout_ms: env_or("ENGINE_ACCOUNT_LOAD_TIMEOUT_MS", 10_000),
+        state_load_timeout_ms: env_or("ENGINE_STATE_LOAD_TIMEOUT_MS", 10_000),
+        default_assets: env_list(
+            "ENGINE_ASSETS",
+            ["BTC_USDC", "ETH_USDC", "SOL_USDC", "BNB_USDC", "DOGE_USDC"]
+                .iter()
+                .map(|asset| asset.to_string())
+                .collect(),
+        ),
+    }
+});
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|raw| raw.parse().ok())
+        .unwrap_or(default)
+}
+
+fn env_list<T: FromStr>(key: &str, default: Vec<T>) -> Vec<T> {
+    env::var(key)
+        .ok()
+        .and_then(|raw| {
+            raw.split(',')
+                .map(|item| item.trim().parse().ok())
+                .collect::<Option<Vec<T>>>()
+        })
+        .filter(|items| !items.is_empty())
+        .unwrap_or(default)
+}
+
+impl EngineConfig {
+    pub fn financing_rate(&self, asset: &str) -> FinancingRate {
+        self.financing_rates
+            .get(asset)
+            .copied()
+            .unwrap_or(self.default_financing_rate)
+    }
+
+    pub fn price_deviation_bps(&self, asset: &str) -> i64 {
+        self.price_deviation_bands
+            .get(asset)
+            .copied()
+            .unwrap_or(self.default_price_deviation_bps)
+    }
+}
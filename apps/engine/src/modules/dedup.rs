@@ -0,0 +1,154 @@
+use crate::modules::config::CONFIG;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// A trade-create request seen by `(user_id, correlation_id)`, with the response it was given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessedRequest {
+    pub seen_at: i64,
+    pub response: Option<(String, String)>, // (message key, payload); None while the order is queued
+}
+
+pub enum Intake<'a> {
+    New,
+    InProgress,                     // first delivery is still waiting on its account load
+    Answered(&'a (String, String)), // first delivery's response, to be sent again
+}
+
+/// Correlation ids of recent trade-create requests, so a redelivered or retried request is
+/// answered from here instead of executing twice. Ids are scoped to the user that sent them,
+/// so one user's id never answers another user's request. Bounded by age and by count.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestDedup {
+    #[serde(with = "dedup_entries")]
+    entries: HashMap<(String, String), ProcessedRequest>, // (user_id, correlation_id) -> request
+    order: VecDeque<(String, String)>, // keys oldest first, for eviction
+}
+
+impl RequestDedup {
+    /// Register `correlation_id` from `user_id` as seen at `now`, or say how it was seen before.
+    pub fn intake(&mut self, user_id: &str, correlation_id: &str, now: i64) -> Intake<'_> {
+        self.evict(now);
+        let key = (user_id.to_string(), correlation_id.to_string());
+        if !self.entries.contains_key(&key) {
+            self.entries.insert(
+                key.clone(),
+                ProcessedRequest {
+                    seen_at: now,
+                    response: None,
+                },
+            );
+            self.order.push_back(key);
+            return Intake::New;
+        }
+        match &self.entries[&key].response {
+            Some(response) => Intake::Answered(response),
+            None => Intake::InProgress,
+        }
+    }
+
+    pub fn record_response(
+        &mut self,
+        user_id: &str,
+        correlation_id: &str,
+        key: &str,
+        response: &str,
+    ) {
+        let id = (user_id.to_string(), correlation_id.to_string());
+        if let Some(entry) = self.entries.get_mut(&id) {
+            entry.response = Some((key.to_string(), response.to_string()));
+        }
+    }
+
+    fn evict(&mut self, now: i64) {
+        while let Some(oldest) = self.order.front() {
+            let expired = self
+                .entries
+                .get(oldest)
+                .is_none_or(|entry| now - entry.seen_at > CONFIG.dedup_window_ms);
+            if !expired && self.order.len() < CONFIG.dedup_max_entries.max(1) {
+                break;
+            }
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+mod dedup_entries {
+    use super::ProcessedRequest;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct DedupEntry {
+        user_id: String,
+        correlation_id: String,
+        #[serde(flatten)]
+        request: ProcessedRequest,
+    }
+
+    pub fn serialize<S: Serializer>(
+        entries: &HashMap<(String, String), ProcessedRequest>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut entries: Vec<DedupEntry> = entries
+            .iter()
+            .map(|((user_id, correlation_id), request)| DedupEntry {
+                user_id: user_id.clone(),
+                correlation_id: correlation_id.clone(),
+                request: request.clone(),
+            })
+            .collect();
+        entries
+            .sort_by(|a, b| (&a.user_id, &a.correlation_id).cmp(&(&b.user_id, &b.correlation_id)));
+        entries.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<(String, String), ProcessedRequest>, D::Error> {
+        let entries = Vec::<DedupEntry>::deserialize(deserializer)?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| ((entry.user_id, entry.correlation_id), entry.request))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn correlation_ids_are_scoped_to_their_user() {
+        let mut dedup = RequestDedup::default();
+        assert!(matches!(dedup.intake("alice", "corr", 0), Intake::New));
+        dedup.record_response("alice", "corr", "order", "accepted");
+
+        assert!(matches!(dedup.intake("bob", "corr", 1), Intake::New));
+        assert!(matches!(
+            dedup.intake("alice", "corr", 2),
+            Intake::Answered((key, _)) if key == "order"
+        ));
+        assert!(matches!(dedup.intake("bob", "corr", 3), Intake::InProgress));
+    }
+
+    #[test]
+    fn survives_a_snapshot_round_trip() {
+        let mut dedup = RequestDedup::default();
+        dedup.intake("alice", "corr", 0);
+        dedup.record_response("alice", "corr", "order", "accepted");
+
+        let json = serde_json::to_string(&dedup).unwrap();
+        let mut restored: RequestDedup = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            restored.intake("alice", "corr", 1),
+            Intake::Answered((_, response)) if response == "accepted"
+        ));
+    }
+}
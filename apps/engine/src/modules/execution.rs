@@ -1,9 +1,14 @@
+use crate::modules::liquidations::open_position_liquidation_price;
+use crate::modules::margin::position_update_outcome;
 use crate::modules::state::EngineState;
-use crate::modules::types::{order_to_trade, Order, OrderStatus, OrderType, Side};
+use crate::modules::terms::start_term;
+use crate::modules::types::{order_to_trade, Order, OrderStatus, OrderType, PositionTerm, Side};
 
 /// Apply an execution to the given user's position for an asset at a price and quantity.
 /// If an opposite position exists, close it (realize PnL, update balance, log).
-/// Otherwise, open a new position at the execution price (PnL=0 on open).
+/// Otherwise, open a new position at the execution price (PnL=0 on open), dated `filled_at`,
+/// with its term running from then.
+#[allow(clippy::too_many_arguments)]
 pub async fn apply_execution(
     engine_state: &mut EngineState,
     user_id: &str,
@@ -16,9 +21,11 @@ pub async fn apply_execution(
     order_type: &OrderType,
     limit_price: Option<i64>,
     margin: i64,
-    created_at: i64,
+    filled_at: i64,
+    term: Option<PositionTerm>,
     tx: &tokio::sync::mpsc::Sender<String>,
 ) {
+    let term = start_term(term, filled_at);
     // Determine if opposite side exists for closing
     let opposite_is_buy = matches!(side_executed, Side::Sell);
     let existing_position = engine_state
@@ -65,8 +72,8 @@ pub async fn apply_execution(
         };
 
         // Update balance with PnL and return margin for closed portion
+        engine_state.realize_pnl(user_id, pnl);
         if let Some(balance) = engine_state.balances.get_mut(user_id) {
-            *balance += pnl;
             *balance += margin_return;
         }
 
@@ -110,6 +117,17 @@ pub async fn apply_execution(
                 engine_state.release_locked_margin(&existing_id);
             } else {
                 engine_state.set_locked_margin(&existing_id, new_margin);
+                // Republish the rest of the position with its reduced margin and liquidation price
+                let remainder = engine_state.open_trades[&existing_id].clone();
+                let trade_outcome = position_update_outcome(
+                    engine_state,
+                    &remainder,
+                    "partially_closed",
+                    filled_at,
+                );
+                if let Ok(json_string) = serde_json::to_string(&trade_outcome) {
+                    let _ = tx.send(json_string).await;
+                }
             }
         }
 
@@ -129,11 +147,14 @@ pub async fn apply_execution(
                 close_price: Some(price),
                 pnl: Some(0),
                 status: Some("filled".to_string()),
-                created_at: Some(created_at),
+                created_at: Some(filled_at),
                 closed_at: None,
                 take_profit_percent: None,
                 stop_loss_percent: None,
                 price: limit_price,
+                term,
+                order_type: Some(order_type.clone()),
+                limit_price,
             };
             engine_state
                 .open_trades
@@ -167,7 +188,7 @@ pub async fn apply_execution(
                 close_price: Some(price),
                 pnl: Some(0),
                 status: Some("filled".to_string()),
-                timestamp: Some(created_at),
+                timestamp: Some(filled_at),
                 margin: Some(remaining_margin),
                 leverage: Some(leverage),
                 slippage: Some(0),
@@ -185,6 +206,11 @@ pub async fn apply_execution(
                     .get(&(user_id.to_string(), asset.to_string()))
                     .copied(),
                 locked_margin: engine_state.locked_margins.get(order_id).copied(),
+                liquidation_price: open_position_liquidation_price(engine_state, order_id),
+                fee: None,
+                term,
+                resting_quantity: None,
+                resting_margin: None,
             };
             if let Ok(json_string) = serde_json::to_string(&trade_outcome) {
                 let _ = tx.send(json_string).await;
@@ -202,7 +228,7 @@ pub async fn apply_execution(
             close_price: Some(price),
             pnl: Some(pnl),
             status: Some("closed".to_string()),
-            timestamp: Some(created_at),
+            timestamp: Some(filled_at),
             margin: Some(margin_return),
             leverage: Some(leverage),
             slippage: Some(0),
@@ -226,6 +252,11 @@ pub async fn apply_execution(
                     .copied()
                     .unwrap_or(0),
             ),
+            liquidation_price: None,
+            fee: None,
+            term: None,
+            resting_quantity: None,
+            resting_margin: None,
         };
         if let Ok(json_string) = serde_json::to_string(&trade_outcome) {
             let _ = tx.send(json_string).await;
@@ -238,7 +269,7 @@ pub async fn apply_execution(
             user_id: user_id.to_string(),
             asset: asset.to_string(),
             side: side_executed.clone(),
-            order_type: OrderType::Market,
+            order_type: order_type.clone(),
             price: Some(price),
             quantity,
             filled: quantity,
@@ -247,11 +278,13 @@ pub async fn apply_execution(
             leverage,
             stop_loss_percent: None,
             take_profit_percent: None,
-            created_at: 0,
+            created_at: filled_at,
             expiry: None,
+            term,
         });
         new_trade.entry_price = Some(price);
         new_trade.close_price = Some(price);
+        new_trade.limit_price = limit_price;
 
         // Update holdings and balance for new position
         match side_executed {
@@ -301,7 +334,7 @@ pub async fn apply_execution(
             close_price: Some(price),
             pnl: Some(0),
             status: Some("filled".to_string()),
-            timestamp: Some(created_at),
+            timestamp: Some(filled_at),
             margin: Some(margin),
             leverage: Some(leverage),
             slippage: Some(0),
@@ -316,6 +349,11 @@ pub async fn apply_execution(
             updated_balance,
             updated_holdings,
             locked_margin,
+            liquidation_price: open_position_liquidation_price(engine_state, order_id),
+            fee: None,
+            term,
+            resting_quantity: None,
+            resting_margin: None,
         };
         if let Ok(json_string) = serde_json::to_string(&trade_outcome) {
             let _ = tx.send(json_string).await;
@@ -351,6 +389,12 @@ pub async fn publish_trade_outcome_for_market_order(
             .unwrap_or(0),
     );
 
+    let liquidation_price = if status == "closed" {
+        None
+    } else {
+        open_position_liquidation_price(engine_state, &order.id)
+    };
+
     let trade_outcome = crate::modules::types::TradeOutcome {
         trade_id: order.id.clone(),
         user_id: order.user_id.clone(),
@@ -361,7 +405,7 @@ pub async fn publish_trade_outcome_for_market_order(
         close_price: Some(close_price),
         pnl: Some(pnl),
         status: Some(status.to_string()),
-        timestamp: Some(order.created_at as i64),
+        timestamp: Some(order.created_at),
         margin: Some(order.margin),
         leverage: Some(order.leverage),
         slippage: Some(0),
@@ -376,6 +420,11 @@ pub async fn publish_trade_outcome_for_market_order(
         updated_balance,
         updated_holdings,
         locked_margin,
+        liquidation_price,
+        fee: None,
+        term: order.term,
+        resting_quantity: None,
+        resting_margin: None,
     };
 
     if let Ok(json_string) = serde_json::to_string(&trade_outcome) {
@@ -0,0 +1,347 @@
+use crate::kafka::producer;
+use crate::modules::config::CONFIG;
+use crate::modules::margin::position_update_outcome;
+use crate::modules::state::{EngineState, SharedEngineState};
+use crate::modules::types::{FinancingCharge, FinancingEvent, Side, TradeOutcome};
+use std::collections::BTreeMap;
+
+const DAY_MS: i64 = 86_400_000;
+
+/// Most recent financing rollover (ms since epoch) at or before `now`.
+pub fn latest_rollover(now: i64) -> i64 {
+    let day_start = now - now.rem_euclid(DAY_MS);
+    let rollover = day_start + CONFIG.financing_rollover_minute * 60_000;
+    if rollover <= now {
+        rollover
+    } else {
+        rollover - DAY_MS
+    }
+}
+
+/// Charge or credit overnight financing on every position opened before a rollover, once
+/// per rollover. Rollovers missed while the engine was down are charged one by one, at current
+/// prices. A position whose market is halted at a rollover has it recorded instead, and charged
+/// once the market trades again. Charges come out of the balance first, then out of the position margin; whatever
+/// both can't cover is absorbed by the insurance fund.
+/// Returns a statement per user and rollover plus the position outcomes that keep the DB in sync.
+pub fn apply_financing(
+    engine_state: &mut EngineState,
+    now: i64,
+) -> (Vec<FinancingEvent>, Vec<TradeOutcome>) {
+    let latest = latest_rollover(now);
+    let due: Vec<i64> = match engine_state.last_financing_rollover {
+        Some(last) => (0..)
+            .map(|days| latest - days * DAY_MS)
+            .take_while(|rollover_at| *rollover_at > last)
+            .collect(),
+        // A fresh state has no earlier rollover to catch up on
+        None => vec![latest],
+    };
+    engine_state.last_financing_rollover = Some(latest);
+
+    let mut events = Vec::new();
+    let mut outcomes = Vec::new();
+    charge_resumed(engine_state, now, &mut events, &mut outcomes);
+    for rollover_at in due.into_iter().rev() {
+        charge_rollover(engine_state, rollover_at, now, &mut events, &mut outcomes);
+    }
+    (events, outcomes)
+}
+
+/// Charge the rollovers positions missed while their market was halted, now that it trades
+/// again. Positions closed in the meantime are forgotten.
+fn charge_resumed(
+    engine_state: &mut EngineState,
+    now: i64,
+    events: &mut Vec<FinancingEvent>,
+    outcomes: &mut Vec<TradeOutcome>,
+) {
+    let open_trades = &engine_state.open_trades;
+    engine_state
+        .missed_rollovers
+        .retain(|trade_id, _| open_trades.contains_key(trade_id));
+    let resumed: Vec<String> = engine_state
+        .missed_rollovers
+        .keys()
+        .filter(|trade_id| {
+            !engine_state.is_market_halted(&engine_state.open_trades[trade_id.as_str()].asset)
+        })
+        .cloned()
+        .collect();
+
+    let mut due: BTreeMap<i64, Vec<String>> = BTreeMap::new();
+    for trade_id in resumed {
+        for rollover_at in engine_state
+            .missed_rollovers
+            .remove(&trade_id)
+            .unwrap_or_default()
+        {
+            due.entry(rollover_at).or_default().push(trade_id.clone());
+        }
+    }
+    for (rollover_at, trade_ids) in due {
+        charge_positions(engine_state, trade_ids, rollover_at, now, events, outcomes);
+    }
+}
+
+fn charge_rollover(
+    engine_state: &mut EngineState,
+    rollover_at: i64,
+    now: i64,
+    events: &mut Vec<FinancingEvent>,
+    outcomes: &mut Vec<TradeOutcome>,
+) {
+    let mut trade_ids = Vec::new();
+    for (trade_id, trade) in &engine_state.open_trades {
+        if trade.created_at.unwrap_or(0) >= rollover_at {
+            continue;
+        }
+        if engine_state.is_market_halted(&trade.asset) {
+            println!(
+                "Financing for order {} deferred: market halted at rollover {}",
+                trade_id, rollover_at
+            );
+            engine_state
+                .missed_rollovers
+                .entry(trade_id.clone())
+                .or_default()
+                .push(rollover_at);
+        } else {
+            trade_ids.push(trade_id.clone());
+        }
+    }
+    charge_positions(engine_state, trade_ids, rollover_at, now, events, outcomes);
+}
+
+/// Charge the financing due at `rollover_at` on each of `trade_ids`, in id order.
+fn charge_positions(
+    engine_state: &mut EngineState,
+    mut trade_ids: Vec<String>,
+    rollover_at: i64,
+    now: i64,
+    events: &mut Vec<FinancingEvent>,
+    outcomes: &mut Vec<TradeOutcome>,
+) {
+    trade_ids.sort();
+
+    let mut charges_by_user: BTreeMap<String, Vec<FinancingCharge>> = BTreeMap::new();
+    for trade_id in trade_ids {
+        let trade = engine_state.open_trades[&trade_id].clone();
+        let price = match engine_state.prices.get(&trade.asset) {
+            Some(price) => *price,
+            None => continue,
+        };
+        let rate = CONFIG.financing_rate(&trade.asset);
+        let rate_bps = match trade.side {
+            Side::Buy => rate.long_bps,
+            Side::Sell => rate.short_bps,
+        };
+        let notional = price as i128 * trade.quantity as i128 * trade.leverage as i128;
+        let charge = (notional * rate_bps as i128 / 10_000) as i64;
+        if charge == 0 {
+            continue;
+        }
+
+        let (from_margin, from_insurance_fund) = settle_financing(engine_state, &trade_id, charge);
+
+        println!(
+            "Financing for order {}: {} bps on notional {} -> {}",
+            trade_id, rate_bps, notional, -charge
+        );
+        if let Some(open_trade) = engine_state.open_trades.get(&trade_id) {
+            outcomes.push(position_update_outcome(
+                engine_state,
+                open_trade,
+                "financing",
+                now,
+            ));
+        }
+        charges_by_user
+            .entry(trade.user_id.clone())
+            .or_default()
+            .push(FinancingCharge {
+                trade_id,
+                asset: trade.asset,
+                side: trade.side,
+                quantity: trade.quantity,
+                leverage: trade.leverage,
+                price,
+                notional: notional as i64,
+                rate_bps,
+                amount: -charge,
+                from_margin,
+                from_insurance_fund,
+            });
+    }
+
+    events.extend(
+        charges_by_user
+            .into_iter()
+            .map(|(user_id, charges)| FinancingEvent {
+                total_amount: charges.iter().map(|c| c.amount).sum(),
+                updated_balance: engine_state.balances.get(&user_id).copied(),
+                user_id,
+                rollover_at,
+                charges,
+                timestamp: now,
+            }),
+    );
+}
+
+/// Take a financing `charge` on position `trade_id` (a negative one is a credit): out of the
+/// user's balance first, then out of the position margin, with the insurance fund covering
+/// whatever both can't. Returns what came out of the margin and out of the insurance fund.
+fn settle_financing(engine_state: &mut EngineState, trade_id: &str, charge: i64) -> (i64, i64) {
+    let trade = engine_state.open_trades[trade_id].clone();
+    let balance = engine_state
+        .balances
+        .get(&trade.user_id)
+        .copied()
+        .unwrap_or(0);
+    let mut from_margin = 0;
+    let mut from_insurance_fund = 0;
+    if charge > 0 {
+        let from_balance = charge.min(balance.max(0));
+        let locked_margin = engine_state.get_locked_margin_or(trade_id, trade.margin);
+        from_margin = (charge - from_balance).min(locked_margin);
+        engine_state.charge_fee(&trade.user_id, from_balance);
+        if from_margin > 0 {
+            engine_state.ledger.fees += from_margin;
+            engine_state.set_locked_margin(trade_id, locked_margin - from_margin);
+            if let Some(open_trade) = engine_state.open_trades.get_mut(trade_id) {
+                open_trade.margin = locked_margin - from_margin;
+            }
+        }
+        from_insurance_fund = charge - from_balance - from_margin;
+        if from_insurance_fund > 0 {
+            engine_state.ledger.fees += from_insurance_fund;
+            engine_state.ledger.insurance_fund -= from_insurance_fund;
+            println!(
+                "Financing for order {} short by {}; covered by the insurance fund",
+                trade_id, from_insurance_fund
+            );
+        }
+    } else {
+        engine_state.charge_fee(&trade.user_id, charge);
+    }
+    (from_margin, from_insurance_fund)
+}
+
+/// Run the financing job against shared state and publish the resulting statements.
+/// Returns whether a rollover was due or missed ones were charged.
+pub async fn process_financing(
+    state: SharedEngineState,
+    tx: tokio::sync::mpsc::Sender<String>,
+    now: i64,
+) -> bool {
+    let mut engine_state = state.lock().await;
+    let last_rollover = engine_state.last_financing_rollover;
+    let missed = engine_state.missed_rollovers.len();
+    let (events, outcomes) = apply_financing(&mut engine_state, now);
+    // Charging missed rollovers on resume only ever takes positions out of the record
+    let changed = engine_state.last_financing_rollover != last_rollover
+        || engine_state.missed_rollovers.len() != missed;
+
+    for event in events {
+        if let Ok(json_string) = serde_json::to_string(&event) {
+            producer::publish_financing_event(&event.user_id, &json_string).await;
+        }
+    }
+    for outcome in outcomes {
+        if let Ok(json_string) = serde_json::to_string(&outcome) {
+            let _ = tx.send(json_string).await;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::invariants::check_funds;
+    use crate::modules::types::Trade;
+
+    fn state_with_position(created_at: i64) -> EngineState {
+        let mut engine_state = EngineState::new();
+        engine_state.balances.insert("user".to_string(), 1_000);
+        engine_state.prices.insert("BTC".to_string(), 100);
+        let trade = Trade {
+            id: "order".to_string(),
+            user_id: "user".to_string(),
+            asset: "BTC".to_string(),
+            side: Side::Buy,
+            margin: 100,
+            leverage: 10,
+            quantity: 1,
+            entry_price: Some(100),
+            close_price: None,
+            pnl: None,
+            status: None,
+            created_at: Some(created_at),
+            closed_at: None,
+            take_profit_percent: None,
+            stop_loss_percent: None,
+            price: None,
+            term: None,
+            order_type: None,
+            limit_price: None,
+        };
+        engine_state.open_trades.insert(trade.id.clone(), trade);
+        engine_state.set_locked_margin("order", 100);
+        engine_state.ledger.deposits = 1_100;
+        engine_state
+    }
+
+    #[test]
+    fn charges_come_out_of_the_balance_then_the_margin_then_the_insurance_fund() {
+        let mut engine_state = state_with_position(0);
+        assert_eq!(settle_financing(&mut engine_state, "order", 40), (0, 0));
+        assert_eq!(engine_state.balances["user"], 960);
+
+        engine_state.balances.insert("user".to_string(), 10);
+        engine_state.ledger.deposits -= 950;
+        assert_eq!(settle_financing(&mut engine_state, "order", 150), (100, 40));
+        assert_eq!(engine_state.balances["user"], 0);
+        assert!(!engine_state.locked_margins.contains_key("order"));
+        assert_eq!(engine_state.open_trades["order"].margin, 0);
+        assert_eq!(engine_state.ledger.insurance_fund, -40);
+        assert_eq!(check_funds(&engine_state).discrepancy(), 0);
+    }
+
+    #[test]
+    fn credits_are_paid_into_the_balance() {
+        let mut engine_state = state_with_position(0);
+        assert_eq!(settle_financing(&mut engine_state, "order", -25), (0, 0));
+        assert_eq!(engine_state.balances["user"], 1_025);
+        assert_eq!(engine_state.locked_margins["order"], 100);
+        assert_eq!(check_funds(&engine_state).discrepancy(), 0);
+    }
+
+    #[test]
+    fn rollovers_missed_on_a_halted_market_are_kept_until_it_resumes() {
+        let rollover = latest_rollover(10 * DAY_MS);
+        let mut engine_state = state_with_position(0);
+        engine_state.last_financing_rollover = Some(rollover - DAY_MS);
+        engine_state.stale_markets.insert("BTC".to_string());
+
+        apply_financing(&mut engine_state, rollover);
+        assert_eq!(engine_state.missed_rollovers["order"], vec![rollover]);
+        apply_financing(&mut engine_state, rollover + 1);
+        assert_eq!(engine_state.missed_rollovers["order"], vec![rollover]);
+
+        engine_state.stale_markets.clear();
+        apply_financing(&mut engine_state, rollover + 2);
+        assert!(engine_state.missed_rollovers.is_empty());
+    }
+
+    #[test]
+    fn missed_rollovers_of_closed_positions_are_dropped() {
+        let mut engine_state = state_with_position(0);
+        engine_state.last_financing_rollover = Some(latest_rollover(DAY_MS));
+        engine_state
+            .missed_rollovers
+            .insert("closed".to_string(), vec![0]);
+        apply_financing(&mut engine_state, DAY_MS);
+        assert!(engine_state.missed_rollovers.is_empty());
+    }
+}
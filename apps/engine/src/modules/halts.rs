@@ -0,0 +1,114 @@
+use crate::kafka::producer;
+use crate::modules::state::EngineState;
+use crate::modules::types::{AdminCommand, HaltEvent, HaltScope, HaltState};
+
+/// What a user is trying to do while a halt may be in force.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltAction {
+    Open,   // any new order
+    Cancel, // cancel a resting order
+    Close,  // close an open position
+}
+
+/// Why `action` is blocked for `user_id` on `asset`, if any halt forbids it.
+pub fn halt_rejection(
+    engine_state: &EngineState,
+    user_id: &str,
+    asset: &str,
+    action: HaltAction,
+) -> Option<String> {
+    engine_state
+        .active_halts(user_id, asset)
+        .into_iter()
+        .find(|(_, halt)| match action {
+            HaltAction::Open => true,
+            HaltAction::Cancel => !halt.allow_cancels,
+            HaltAction::Close => !halt.allow_closes,
+        })
+        .map(|(scope, halt)| {
+            let scope = match scope {
+                HaltScope::Global => "all trading".to_string(),
+                HaltScope::Asset => asset.to_string(),
+                HaltScope::User => "this account".to_string(),
+            };
+            match &halt.reason {
+                Some(reason) => format!("Trading halted for {}: {}", scope, reason),
+                None => format!("Trading halted for {}", scope),
+            }
+        })
+}
+
+/// Apply a halt or lift-halt admin command. Returns the transition to publish, or why the
+/// command was refused. Re-halting an already halted scope updates its flags and reason.
+pub fn apply_halt_command(
+    engine_state: &mut EngineState,
+    command: &AdminCommand,
+    timestamp: i64,
+) -> Result<HaltEvent, &'static str> {
+    match command {
+        AdminCommand::Halt {
+            scope,
+            target,
+            allow_cancels,
+            allow_closes,
+            reason,
+        } => {
+            let halt = HaltState {
+                allow_cancels: *allow_cancels,
+                allow_closes: *allow_closes,
+                reason: reason.clone(),
+                since: timestamp,
+            };
+            let previous = match (scope, target) {
+                (HaltScope::Global, _) => engine_state.global_halt.replace(halt),
+                (HaltScope::Asset, Some(asset)) => {
+                    engine_state.asset_halts.insert(asset.clone(), halt)
+                }
+                (HaltScope::User, Some(user_id)) => {
+                    engine_state.user_halts.insert(user_id.clone(), halt)
+                }
+                _ => return Err("Target required for asset and user halts"),
+            };
+            Ok(HaltEvent {
+                scope: *scope,
+                target: target.clone(),
+                status: if previous.is_some() {
+                    "updated"
+                } else {
+                    "halted"
+                }
+                .to_string(),
+                allow_cancels: *allow_cancels,
+                allow_closes: *allow_closes,
+                reason: reason.clone(),
+                timestamp,
+            })
+        }
+        AdminCommand::LiftHalt { scope, target } => {
+            let lifted = match (scope, target) {
+                (HaltScope::Global, _) => engine_state.global_halt.take(),
+                (HaltScope::Asset, Some(asset)) => engine_state.asset_halts.remove(asset),
+                (HaltScope::User, Some(user_id)) => engine_state.user_halts.remove(user_id),
+                _ => return Err("Target required for asset and user halts"),
+            }
+            .ok_or("No halt in force for this scope")?;
+            Ok(HaltEvent {
+                scope: *scope,
+                target: target.clone(),
+                status: "lifted".to_string(),
+                allow_cancels: true,
+                allow_closes: true,
+                reason: lifted.reason,
+                timestamp,
+            })
+        }
+        _ => Err("Not a halt command"),
+    }
+}
+
+pub async fn publish_halt_event(event: &HaltEvent) {
+    if let Ok(json_string) = serde_json::to_string(event) {
+        let key = event.target.as_deref().unwrap_or("global");
+        producer::publish_trading_halt(key, &json_string).await;
+    }
+}
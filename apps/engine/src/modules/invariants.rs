@@ -0,0 +1,243 @@
+use crate::modules::config::CONFIG;
+use crate::modules::journal::JOURNAL;
+use crate::modules::sequencer::{submit_task, EventSender, SequencerTask};
+use crate::modules::state::{EngineState, SharedEngineState};
+use crate::modules::types::Ledger;
+use tokio::time::{sleep, Duration};
+
+/// Where user funds stand against the ledger at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FundsCheck {
+    pub ledger: Ledger,
+    pub balances: i64,
+    pub locked_margin: i64,          // margin backing open positions
+    pub resting_margin: i64,         // margin reserved by orders on the books
+    pub orphaned_locks: Vec<String>, // locked margin entries without an open position
+}
+
+impl FundsCheck {
+    /// deposits + adjustments + realized PnL - (balances + locked + resting + fees +
+    /// insurance fund). Zero when every unit of money is accounted for.
+    pub fn discrepancy(&self) -> i64 {
+        self.ledger.deposits + self.ledger.adjustments + self.ledger.realized_pnl
+            - (self.balances
+                + self.locked_margin
+                + self.resting_margin
+                + self.ledger.fees
+                + self.ledger.insurance_fund)
+    }
+
+    pub fn violations(&self) -> Vec<String> {
+        let mut violations = Vec::new();
+        let discrepancy = self.discrepancy();
+        if discrepancy != 0 {
+            violations.push(format!(
+                "funds not conserved by {}: deposits {} + adjustments {} + realized PnL {} != balances {} + locked margin {} + resting margin {} + fees {} + insurance fund {}",
+                discrepancy,
+                self.ledger.deposits,
+                self.ledger.adjustments,
+                self.ledger.realized_pnl,
+                self.balances,
+                self.locked_margin,
+                self.resting_margin,
+                self.ledger.fees,
+                self.ledger.insurance_fund
+            ));
+        }
+        if !self.orphaned_locks.is_empty() {
+            violations.push(format!(
+                "margin locked for closed positions: {}",
+                self.orphaned_locks.join(", ")
+            ));
+        }
+        violations
+    }
+}
+
+pub fn check_funds(engine_state: &EngineState) -> FundsCheck {
+    let locked_margin = engine_state
+        .open_trades
+        .iter()
+        .map(|(id, trade)| engine_state.get_locked_margin_or(id, trade.margin))
+        .sum();
+    let resting_margin = engine_state
+        .order_books
+        .values()
+        .flat_map(|book| book.buy.values().chain(book.sell.values()))
+        .flatten()
+        .map(|order| order.margin)
+        .sum();
+    let mut orphaned_locks: Vec<String> = engine_state
+        .locked_margins
+        .keys()
+        .filter(|id| !engine_state.open_trades.contains_key(*id))
+        .cloned()
+        .collect();
+    orphaned_locks.sort();
+
+    FundsCheck {
+        ledger: engine_state.ledger,
+        balances: engine_state.balances.values().sum(),
+        locked_margin,
+        resting_margin,
+        orphaned_locks,
+    }
+}
+
+/// Remembers the last check so a violation is reported once, against the event that caused
+/// it, instead of after every event that follows.
+#[derive(Default)]
+pub struct InvariantMonitor {
+    last: Option<FundsCheck>,
+    checked_seq: u64, // journal entry the last check ran after
+}
+
+impl InvariantMonitor {
+    /// Check `engine_state` and report any violation that is new since the last check, along
+    /// with any balance drift booked in between. `context` names what was applied in between.
+    /// Returns the violations reported.
+    pub fn observe(&mut self, engine_state: &EngineState, context: &str) -> Vec<String> {
+        let check = check_funds(engine_state);
+        let drift =
+            check.ledger.adjustments - self.last.as_ref().map_or(0, |last| last.ledger.adjustments);
+        if drift != 0 {
+            println!("Balance drift of {} adjusted after {}", drift, context);
+        }
+        let changed = match &self.last {
+            Some(last) => {
+                last.discrepancy() != check.discrepancy()
+                    || check
+                        .orphaned_locks
+                        .iter()
+                        .any(|id| !last.orphaned_locks.contains(id))
+            }
+            None => true,
+        };
+        let reported = if changed {
+            check.violations()
+        } else {
+            Vec::new()
+        };
+        for violation in &reported {
+            eprintln!("Invariant violated after {}: {}", context, violation);
+        }
+        self.last = Some(check);
+        self.checked_seq = engine_state.journal_seq;
+        reported
+    }
+}
+
+/// Check funds against everything applied since the last check. Holds the journal so no event
+/// is half-applied while the check runs.
+pub async fn check_invariants(state: &SharedEngineState) {
+    let mut journal = JOURNAL.lock().await;
+    let engine_state = state.lock().await;
+    let context = format!(
+        "journal entries #{}..=#{}",
+        journal.invariants.checked_seq + 1,
+        engine_state.journal_seq
+    );
+    journal.invariants.observe(&engine_state, &context);
+}
+
+/// Call this once at startup to have the sequencer check funds at the configured interval.
+pub fn spawn_invariant_checker(events: EventSender) {
+    tokio::spawn(async move {
+        loop {
+            sleep(Duration::from_millis(
+                CONFIG.invariant_check_interval_ms.max(1) as u64,
+            ))
+            .await;
+            submit_task(&events, SequencerTask::InvariantCheck).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::types::{Side, Trade};
+
+    fn funded_state() -> EngineState {
+        let mut engine_state = EngineState::new();
+        engine_state.load_balance("user", 1_000);
+        engine_state
+    }
+
+    fn open_position(engine_state: &mut EngineState, id: &str, margin: i64) {
+        let trade = Trade {
+            id: id.to_string(),
+            user_id: "user".to_string(),
+            asset: "BTC".to_string(),
+            side: Side::Buy,
+            margin,
+            leverage: 1,
+            quantity: 1,
+            entry_price: Some(100),
+            close_price: None,
+            pnl: None,
+            status: None,
+            created_at: Some(0),
+            closed_at: None,
+            take_profit_percent: None,
+            stop_loss_percent: None,
+            price: None,
+            term: None,
+            order_type: None,
+            limit_price: None,
+        };
+        *engine_state.balances.get_mut("user").unwrap() -= margin;
+        engine_state.set_locked_margin(id, margin);
+        engine_state.open_trades.insert(id.to_string(), trade);
+    }
+
+    #[test]
+    fn balanced_state_has_no_violations() {
+        let mut engine_state = funded_state();
+        open_position(&mut engine_state, "order", 100);
+        engine_state.realize_pnl("user", -30);
+        engine_state.charge_fee("user", 5);
+
+        let check = check_funds(&engine_state);
+        assert_eq!(check.discrepancy(), 0);
+        assert!(check.violations().is_empty());
+    }
+
+    #[test]
+    fn balance_changed_outside_the_ledger_is_reported_once() {
+        let mut engine_state = funded_state();
+        let mut monitor = InvariantMonitor::default();
+        assert!(monitor.observe(&engine_state, "load").is_empty());
+
+        *engine_state.balances.get_mut("user").unwrap() += 50;
+        let reported = monitor.observe(&engine_state, "injected credit");
+        assert_eq!(reported.len(), 1);
+        assert!(reported[0].starts_with("funds not conserved by -50"));
+        assert!(monitor.observe(&engine_state, "next event").is_empty());
+    }
+
+    #[test]
+    fn margin_locked_for_a_closed_position_is_reported() {
+        let mut engine_state = funded_state();
+        open_position(&mut engine_state, "order", 100);
+        engine_state.open_trades.remove("order");
+
+        let check = check_funds(&engine_state);
+        assert_eq!(check.orphaned_locks, vec!["order".to_string()]);
+        assert!(check
+            .violations()
+            .iter()
+            .any(|violation| violation.starts_with("margin locked for closed positions")));
+    }
+
+    #[test]
+    fn reloading_a_balance_books_drift_not_a_deposit() {
+        let mut engine_state = funded_state();
+        engine_state.load_balance("user", 1_200);
+        engine_state.load_balance("other", 300);
+
+        assert_eq!(engine_state.ledger.deposits, 1_300);
+        assert_eq!(engine_state.ledger.adjustments, 200);
+        assert_eq!(check_funds(&engine_state).discrepancy(), 0);
+    }
+}
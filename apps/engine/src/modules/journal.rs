@@ -0,0 +1,559 @@
+use crate::kafka::producer;
+use crate::modules::account_loads::process_account_load_timeouts;
+use crate::modules::assets::process_admin_request;
+use crate::modules::candles::process_candle_closes;
+use crate::modules::closing::process_trade_close;
+use crate::modules::config::CONFIG;
+use crate::modules::financing::process_financing;
+use crate::modules::invariants::InvariantMonitor;
+use crate::modules::margin::{
+    process_leverage_change, process_margin_mode_change, process_position_margin_change,
+};
+use crate::modules::market_status::monitor_price_staleness;
+use crate::modules::price_store::PRICE_STORE;
+use crate::modules::price_updater::handle_price_update;
+use crate::modules::processor::{
+    process_balance_response, process_holdings_response, process_trade_create,
+};
+use crate::modules::reconciliation::{process_reconciliation, process_reconciliation_answer};
+use crate::modules::state::SharedEngineState;
+use crate::modules::stop_loss_take_profit::monitor_stop_loss_take_profit;
+use crate::modules::terms::process_term_expiries;
+use crate::modules::types::{
+    AdminRequest, CloseTradeRequest, CreateTradeRequest, LeverageChangeRequest, MarginModeRequest,
+    PositionMarginRequest, ReconciliationKey, StagedTick,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::Mutex;
+
+/// Periodic jobs that mutate state. Their only input is the time they ran at.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimerJob {
+    StopLossTakeProfit,
+    CandleClose,
+    PriceStaleness,
+    Financing,
+    TermExpiry,
+    Reconciliation,
+    AccountLoadTimeout,
+}
+
+/// Every input that mutates `EngineState`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    content = "payload",
+    rename_all = "snake_case",
+    rename_all_fields = "camelCase"
+)]
+pub enum JournalEvent {
+    TradeCreate(CreateTradeRequest),
+    TradeClose(CloseTradeRequest),
+    PriceUpdate(StagedTick), // tick the price store accepted, with the range of those it replaced
+    BalanceResponse {
+        user_id: String,
+        balance: i64,
+    },
+    HoldingsResponse {
+        user_id: String,
+        asset: String,
+        held_quantity: i64,
+    },
+    ReconciliationAnswer {
+        run_id: String,
+        key: ReconciliationKey,
+        database: i64, // value the DB holds for `key`
+    },
+    MarginMode(MarginModeRequest),
+    PositionMargin(PositionMarginRequest),
+    Leverage(LeverageChangeRequest),
+    Admin(AdminRequest),
+    Timer(TimerJob),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub seq: u64,
+    pub recorded_at: i64, // the `now` the event is processed with, live and on replay
+    pub event: JournalEvent,
+}
+
+pub struct Journal {
+    file: Option<File>,
+    next_seq: u64,
+    unsynced: bool, // entries written since the last fsync
+    pub invariants: InvariantMonitor,
+}
+
+impl Journal {
+    /// Write `entry` without syncing it; `sync` makes it durable. A failed write is cut off
+    /// again so it can't leave a torn line ahead of later entries.
+    fn write(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let file = self
+            .file
+            .as_mut()
+            .ok_or_else(|| io::Error::other("journal is not open"))?;
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let start_len = file.metadata()?.len();
+        if let Err(e) = file.write_all(&line) {
+            let _ = file.set_len(start_len);
+            return Err(e);
+        }
+        self.unsynced = true;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if let (true, Some(file)) = (self.unsynced, self.file.as_mut()) {
+            file.sync_data()?;
+        }
+        self.unsynced = false;
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        match self.file.as_ref() {
+            Some(file) => Ok(file.metadata()?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Write and sync `events` as one group, numbering them from `next_seq`. On failure the
+    /// group is cut off again and nothing of it counts as journaled.
+    fn commit(&mut self, events: Vec<JournalEvent>, now: i64) -> io::Result<Vec<JournalEntry>> {
+        let start_len = self.len()?;
+        let entries: Vec<JournalEntry> = events
+            .into_iter()
+            .zip(self.next_seq..)
+            .map(|(event, seq)| JournalEntry {
+                seq,
+                recorded_at: now,
+                event,
+            })
+            .collect();
+        let written = entries
+            .iter()
+            .try_for_each(|entry| self.write(entry))
+            .and_then(|_| self.sync());
+        if let Err(e) = written {
+            if let Some(file) = self.file.as_mut() {
+                let _ = file.set_len(start_len);
+            }
+            self.unsynced = false;
+            return Err(e);
+        }
+        self.next_seq += entries.len() as u64;
+        Ok(entries)
+    }
+
+    /// Drop every entry; called once a snapshot covers them all.
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.unsynced = false;
+        match self.file.as_mut() {
+            Some(file) => {
+                file.set_len(0)?;
+                file.sync_all()
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Held for the whole of journaling and applying a batch of events, so events are applied one
+/// at a time in journal order and a snapshot never sees one half-applied.
+pub static JOURNAL: Lazy<Mutex<Journal>> = Lazy::new(|| {
+    Mutex::new(Journal {
+        file: None,
+        next_seq: 1,
+        unsynced: false,
+        invariants: InvariantMonitor::default(),
+    })
+});
+
+/// Throw away the journal. Used when state was loaded from the DB instead of a snapshot,
+/// since the journal's entries describe a state the engine no longer starts from.
+pub fn discard_journal() -> io::Result<()> {
+    match fs::remove_file(&CONFIG.journal_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Open the journal for appending after the entry numbered `last_seq`.
+pub async fn open_journal(last_seq: u64) -> io::Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&CONFIG.journal_path)?;
+    let mut journal = JOURNAL.lock().await;
+    journal.file = Some(file);
+    journal.next_seq = last_seq + 1;
+    Ok(())
+}
+
+/// Journal and apply a batch of events in order. Only the sequencer calls this once startup is
+/// done. Inputs are written ahead with one fsync per run of them; an input that can't be
+/// journaled is dropped rather than applied, since it would be lost on restart.
+/// Timer jobs depend only on state and time, so they are applied first and journaled only if
+/// they changed state. Their entries are synced with the next group; if the engine stops before
+/// that, the job simply runs again on the restored state.
+pub async fn process_batch(
+    state: &SharedEngineState,
+    events: Vec<JournalEvent>,
+    tx: &Sender<String>,
+) {
+    let mut journal = JOURNAL.lock().await;
+    let mut inputs = Vec::new();
+    for event in events {
+        match event {
+            JournalEvent::Timer(job) => {
+                commit_and_apply(state, &mut journal, std::mem::take(&mut inputs), tx).await;
+                apply_timer(state, &mut journal, job, tx).await;
+            }
+            event => inputs.push(event),
+        }
+    }
+    commit_and_apply(state, &mut journal, inputs, tx).await;
+    if let Err(e) = journal.sync() {
+        eprintln!("Failed to sync journal: {}", e);
+    }
+}
+
+async fn commit_and_apply(
+    state: &SharedEngineState,
+    journal: &mut Journal,
+    inputs: Vec<JournalEvent>,
+    tx: &Sender<String>,
+) {
+    if inputs.is_empty() {
+        return;
+    }
+    let count = inputs.len();
+    let entries = match journal.commit(inputs, chrono::Utc::now().timestamp_millis()) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Failed to journal {} events, dropping them: {}", count, e);
+            return;
+        }
+    };
+    for entry in entries {
+        apply_entry(state, entry, tx, &mut journal.invariants).await;
+    }
+}
+
+async fn apply_timer(
+    state: &SharedEngineState,
+    journal: &mut Journal,
+    job: TimerJob,
+    tx: &Sender<String>,
+) {
+    let entry = JournalEntry {
+        seq: journal.next_seq,
+        recorded_at: chrono::Utc::now().timestamp_millis(),
+        event: JournalEvent::Timer(job),
+    };
+    if !apply_event(state, entry.event.clone(), entry.recorded_at, tx).await {
+        return;
+    }
+    if let Err(e) = journal.write(&entry) {
+        eprintln!("Failed to journal {:?}: {}", entry.event, e);
+    }
+    // State has moved on either way; a gap in the numbering is harmless on replay
+    journal.next_seq += 1;
+    finish_entry(state, &entry, &mut journal.invariants).await;
+}
+
+async fn apply_entry(
+    state: &SharedEngineState,
+    entry: JournalEntry,
+    tx: &Sender<String>,
+    invariants: &mut InvariantMonitor,
+) {
+    apply_event(state, entry.event.clone(), entry.recorded_at, tx).await;
+    finish_entry(state, &entry, invariants).await;
+}
+
+/// Record `entry` as the last one applied to state.
+async fn finish_entry(
+    state: &SharedEngineState,
+    entry: &JournalEntry,
+    invariants: &mut InvariantMonitor,
+) {
+    let mut engine_state = state.lock().await;
+    engine_state.journal_seq = entry.seq;
+    // When enabled, funds are checked after every event to pin a violation on its cause;
+    // otherwise the periodic check covers the entries since it last ran
+    if CONFIG.invariant_check_every_event {
+        let context = format!("journal entry #{} {:?}", entry.seq, entry.event);
+        invariants.observe(&engine_state, &context);
+    }
+}
+
+/// Apply one event at `now`. Returns whether state may have changed: always for inputs, and
+/// for timer jobs only when they acted.
+async fn apply_event(
+    state: &SharedEngineState,
+    event: JournalEvent,
+    now: i64,
+    tx: &Sender<String>,
+) -> bool {
+    match event {
+        JournalEvent::TradeCreate(req) => {
+            process_trade_create(state.clone(), req, tx.clone(), now).await
+        }
+        JournalEvent::TradeClose(req) => process_trade_close(state.clone(), req, tx.clone()).await,
+        JournalEvent::PriceUpdate(tick) => handle_price_update(tick, state.clone(), now).await,
+        JournalEvent::BalanceResponse { user_id, balance } => {
+            process_balance_response(state.clone(), user_id, balance, tx.clone(), now).await
+        }
+        JournalEvent::HoldingsResponse {
+            user_id,
+            asset,
+            held_quantity,
+        } => {
+            process_holdings_response(
+                state.clone(),
+                user_id,
+                asset,
+                held_quantity,
+                tx.clone(),
+                now,
+            )
+            .await
+        }
+        JournalEvent::ReconciliationAnswer {
+            run_id,
+            key,
+            database,
+        } => process_reconciliation_answer(state.clone(), run_id, key, database, now).await,
+        JournalEvent::MarginMode(req) => process_margin_mode_change(state.clone(), req).await,
+        JournalEvent::PositionMargin(req) => {
+            process_position_margin_change(state.clone(), req, tx.clone()).await
+        }
+        JournalEvent::Leverage(req) => {
+            process_leverage_change(state.clone(), req, tx.clone()).await
+        }
+        JournalEvent::Admin(req) => process_admin_request(state.clone(), req, tx.clone()).await,
+        JournalEvent::Timer(job) => {
+            return match job {
+                TimerJob::StopLossTakeProfit => {
+                    monitor_stop_loss_take_profit(state.clone(), tx.clone(), now).await
+                }
+                TimerJob::CandleClose => process_candle_closes(state.clone(), now).await,
+                TimerJob::PriceStaleness => monitor_price_staleness(state.clone(), now).await,
+                TimerJob::Financing => process_financing(state.clone(), tx.clone(), now).await,
+                TimerJob::TermExpiry => process_term_expiries(state.clone(), tx.clone(), now).await,
+                TimerJob::Reconciliation => process_reconciliation(state.clone(), now).await,
+                TimerJob::AccountLoadTimeout => {
+                    process_account_load_timeouts(state.clone(), now).await
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Re-apply the journal entries the restored state hasn't seen, without publishing anything.
+/// A torn final line from a crash mid-append is cut off. Returns the last applied entry.
+/// The price store is seeded from the restored state first, so replayed executions price off
+/// the same bid and ask they did live.
+pub async fn replay_journal(state: &SharedEngineState) -> io::Result<u64> {
+    replay_journal_at(state, &CONFIG.journal_path).await
+}
+
+async fn replay_journal_at(state: &SharedEngineState, path: &str) -> io::Result<u64> {
+    let mut last_seq = {
+        let engine_state = state.lock().await;
+        PRICE_STORE.seed(&engine_state);
+        engine_state.journal_seq
+    };
+    if !Path::new(path).exists() {
+        return Ok(last_seq);
+    }
+
+    // Outcomes go nowhere: the receiver is dropped before anything is sent
+    let (tx, _) = mpsc::channel::<String>(1);
+    producer::set_replaying(true);
+
+    let mut journal = JOURNAL.lock().await;
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut valid_len = 0u64;
+    let mut replayed = 0usize;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        let entry = match serde_json::from_str::<JournalEntry>(line.trim_end()) {
+            Ok(entry) if line.ends_with('\n') => entry,
+            _ => {
+                eprintln!(
+                    "Discarding torn journal tail at byte {} of {}",
+                    valid_len, path
+                );
+                fs::OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_len(valid_len)?;
+                break;
+            }
+        };
+        valid_len += read as u64;
+        if entry.seq <= last_seq {
+            continue;
+        }
+        last_seq = entry.seq;
+        apply_entry(state, entry, &tx, &mut journal.invariants).await;
+        replayed += 1;
+    }
+
+    producer::set_replaying(false);
+    println!(
+        "Replayed {} journal entries from {}, up to #{}",
+        replayed, path, last_seq
+    );
+    Ok(last_seq)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::state::EngineState;
+    use crate::modules::types::{AssetInfo, AssetStatus, OrderType, PriceUpdate, Side};
+    use std::sync::Arc;
+
+    fn tick(price: i64, timestamp: i64) -> JournalEvent {
+        JournalEvent::PriceUpdate(StagedTick {
+            update: PriceUpdate {
+                asset: "SOL".to_string(),
+                price,
+                bid: price - 1,
+                ask: price + 1,
+                timestamp,
+                source: "backpack".to_string(),
+            },
+            high: price + 2,
+            low: price - 2,
+        })
+    }
+
+    fn order(user_id: &str, side: Side, limit_price: Option<i64>) -> JournalEvent {
+        JournalEvent::TradeCreate(CreateTradeRequest {
+            user_id: user_id.to_string(),
+            correlation_id: Some(format!("{}-{:?}-{:?}", user_id, side, limit_price)),
+            asset: "SOL".to_string(),
+            side,
+            margin: 200,
+            leverage: 5,
+            slippage: None,
+            order_type: Some(match limit_price {
+                Some(_) => OrderType::Limit,
+                None => OrderType::Market,
+            }),
+            limit_price,
+            stop_loss_percent: None,
+            take_profit_percent: None,
+            trade_term: None,
+            term_rollover: None,
+            time_in_force: None,
+            expiry_timestamp: None,
+            timestamp: 0,
+            quantity: Some(2),
+        })
+    }
+
+    fn initial_state() -> EngineState {
+        let mut engine_state = EngineState::new();
+        engine_state.assets.insert(
+            "SOL".to_string(),
+            AssetInfo {
+                status: AssetStatus::Trading,
+                max_leverage: None,
+            },
+        );
+        for user_id in ["maker", "taker"] {
+            engine_state.load_balance(user_id, 10_000);
+        }
+        // The taker's holdings are only loaded by an entry in the journal
+        engine_state
+            .holdings
+            .insert(("maker".to_string(), "SOL".to_string()), 0);
+        engine_state
+    }
+
+    fn restore(snapshot: &str) -> SharedEngineState {
+        Arc::new(Mutex::new(serde_json::from_str(snapshot).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn replaying_the_journal_onto_a_snapshot_reproduces_the_live_state() {
+        producer::set_replaying(true);
+        let events = vec![
+            tick(100, 1_000),
+            order("maker", Side::Sell, Some(120)),
+            order("taker", Side::Buy, None),
+            JournalEvent::HoldingsResponse {
+                user_id: "taker".to_string(),
+                asset: "SOL".to_string(),
+                held_quantity: 0,
+            },
+            tick(110, 2_000),
+            order("taker", Side::Buy, Some(120)),
+            JournalEvent::Timer(TimerJob::Financing),
+        ];
+        let entries: Vec<JournalEntry> = events
+            .into_iter()
+            .zip(1..)
+            .map(|(event, seq)| JournalEntry {
+                seq,
+                recorded_at: seq as i64 * 1_000,
+                event,
+            })
+            .collect();
+
+        // Live: a snapshot is taken after the second entry, the rest is only journaled
+        let live = restore(&serde_json::to_string(&initial_state()).unwrap());
+        PRICE_STORE.seed(&*live.lock().await);
+        let (tx, _) = mpsc::channel::<String>(1);
+        let mut invariants = InvariantMonitor::default();
+        let mut snapshot = String::new();
+        for entry in &entries {
+            apply_entry(&live, entry.clone(), &tx, &mut invariants).await;
+            if entry.seq == 2 {
+                snapshot = serde_json::to_string(&*live.lock().await).unwrap();
+            }
+        }
+
+        let path =
+            std::env::temp_dir().join(format!("engine-journal-{}.jsonl", std::process::id()));
+        let journal: String = entries
+            .iter()
+            .map(|entry| serde_json::to_string(entry).unwrap() + "\n")
+            .collect();
+        fs::write(&path, journal).unwrap();
+
+        let replayed = restore(&snapshot);
+        let last_seq = replay_journal_at(&replayed, path.to_str().unwrap())
+            .await
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(last_seq, 7);
+        let live = live.lock().await;
+        assert!(!live.open_trades.is_empty());
+        assert_eq!(
+            serde_json::to_value(&*live).unwrap(),
+            serde_json::to_value(&*replayed.lock().await).unwrap()
+        );
+    }
+}
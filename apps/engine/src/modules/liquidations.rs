@@ -1,38 +1,197 @@
 use crate::modules::pnl::calculate_pnl; // Updated import for pnl
+use crate::modules::settlement::close_trade_at_price;
 use crate::modules::state::EngineState; // Updated import for state
 
 /// Check if liquidation is needed for a trade
-use crate::modules::types::Trade;
+use crate::modules::types::{MarginMode, Side, Trade, TradeOutcome};
 
 pub fn check_liquidation(trade: &Trade, latest_price: i64) -> bool {
-    let maintenance_margin_percent = get_maintenance_margin_percent(trade.margin);
+    let unrealized_pnl = unrealized_pnl(trade, latest_price);
 
+    let current_margin = trade.margin + unrealized_pnl;
+    let maintenance_margin = maintenance_margin(trade.margin);
+
+    current_margin < maintenance_margin
+}
+
+/// Liquidate a trade, returning at most its locked margin to the balance
+pub fn liquidate_trade(
+    state: &mut EngineState,
+    order_id: &str,
+    latest_price: i64,
+    timestamp: i64,
+) -> Option<TradeOutcome> {
+    close_trade_at_price(
+        state,
+        order_id,
+        latest_price,
+        "liquidated",
+        Some("maintenance_margin"),
+        true,
+        timestamp,
+    )
+}
+
+/// Price at which `check_liquidation` first fires for a trade carrying `margin`:
+/// longs liquidate at or below it, shorts at or above it.
+/// `None` when the position is empty or cannot be liquidated at a positive price.
+pub fn liquidation_price(trade: &Trade, margin: i64) -> Option<i64> {
+    let entry_price = trade.entry_price?;
+    let exposure = trade.quantity * trade.leverage;
+    if exposure <= 0 {
+        return None;
+    }
+    // Largest adverse move (in whole price units) the margin absorbs above maintenance
+    let buffer = (margin - maintenance_margin(margin)).max(0);
+    let tolerated_move = buffer / exposure;
+    let price = match trade.side {
+        Side::Buy => entry_price - tolerated_move - 1,
+        Side::Sell => entry_price + tolerated_move + 1,
+    };
+    if price > 0 {
+        Some(price)
+    } else {
+        None
+    }
+}
+
+/// Liquidation price published for `trade` carrying `margin`. Cross-margin positions have
+/// none: their account is liquidated on its equity, not at a price per position.
+pub fn position_liquidation_price(state: &EngineState, trade: &Trade, margin: i64) -> Option<i64> {
+    if state.margin_mode(&trade.user_id) == MarginMode::Cross {
+        return None;
+    }
+    liquidation_price(trade, margin)
+}
+
+/// Liquidation price of an open position from its current locked margin, if it is still open
+pub fn open_position_liquidation_price(state: &EngineState, trade_id: &str) -> Option<i64> {
+    let trade = state.open_trades.get(trade_id)?;
+    position_liquidation_price(
+        state,
+        trade,
+        state.get_locked_margin_or(trade_id, trade.margin),
+    )
+}
+
+/// Unrealized PnL of a trade marked at `latest_price`
+pub fn unrealized_pnl(trade: &Trade, latest_price: i64) -> i64 {
     // Clone trade and set close_price for PnL calculation
     let mut temp_trade = trade.clone();
     temp_trade.close_price = Some(latest_price);
-    let unrealized_pnl = calculate_pnl(&temp_trade);
+    calculate_pnl(&temp_trade)
+}
 
-    let current_margin = trade.margin + unrealized_pnl;
-    let maintenance_margin = (trade.margin * maintenance_margin_percent) / 100;
+/// Maintenance margin required to keep a position with the given margin open
+pub fn maintenance_margin(margin: i64) -> i64 {
+    (margin * get_maintenance_margin_percent(margin)) / 100
+}
 
-    current_margin < maintenance_margin
+/// Account equity of a user: free balance plus the locked margin and unrealized PnL
+/// of every open position. Positions without a price contribute no PnL.
+pub fn account_equity(state: &EngineState, user_id: &str) -> i64 {
+    let balance = state.balances.get(user_id).copied().unwrap_or(0);
+    state
+        .open_trades
+        .iter()
+        .filter(|(_, trade)| trade.user_id == user_id)
+        .fold(balance, |equity, (id, trade)| {
+            let locked_margin = state.get_locked_margin_or(id, trade.margin);
+            let pnl = state
+                .exit_price(trade)
+                .map(|price| unrealized_pnl(trade, price))
+                .unwrap_or(0);
+            equity + locked_margin + pnl
+        })
 }
 
-/// Liquidate a trade
-pub fn liquidate_trade(state: &mut EngineState, order_id: &str, latest_price: i64) {
-    if let Some(mut trade) = state.open_trades.remove(order_id) {
-        // Set close_price for accurate PnL
-        trade.close_price = Some(latest_price);
-        let pnl = calculate_pnl(&trade);
+/// Total maintenance margin across all open positions of a user
+pub fn account_maintenance_margin(state: &EngineState, user_id: &str) -> i64 {
+    state
+        .open_trades
+        .iter()
+        .filter(|(_, trade)| trade.user_id == user_id)
+        .map(|(id, trade)| maintenance_margin(state.get_locked_margin_or(id, trade.margin)))
+        .sum()
+}
+
+/// Positions to liquidate for a cross-margin account whose equity fell below its total
+/// maintenance margin, as (order_id, price) pairs in risk-reducing order: largest
+/// unrealized loss first, then largest maintenance requirement. Empty when the account is healthy.
+pub fn cross_liquidation_queue(state: &EngineState, user_id: &str) -> Vec<(String, i64)> {
+    let equity = account_equity(state, user_id);
+    let mut required = account_maintenance_margin(state, user_id);
+    if equity >= required {
+        return Vec::new();
+    }
+
+    let mut candidates: Vec<(String, i64, i64, i64)> = state
+        .open_trades
+        .iter()
+        .filter(|(_, trade)| trade.user_id == user_id)
+        .filter_map(|(id, trade)| {
+            let price = state.exit_price(trade)?;
+            let locked_margin = state.get_locked_margin_or(id, trade.margin);
+            Some((
+                id.clone(),
+                price,
+                unrealized_pnl(trade, price),
+                maintenance_margin(locked_margin),
+            ))
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.2.cmp(&b.2).then(b.3.cmp(&a.3)));
+
+    // Closing at the mark leaves equity unchanged and releases that position's requirement
+    let mut queue = Vec::new();
+    for (id, price, _, position_maintenance) in candidates {
+        if equity >= required {
+            break;
+        }
+        required -= position_maintenance;
+        queue.push((id, price));
+    }
+    queue
+}
+
+/// Liquidate the queued positions of a cross-margin account. Losses are settled against
+/// the shared balance, which is floored at zero once the account is flat.
+pub fn liquidate_cross_account(
+    state: &mut EngineState,
+    user_id: &str,
+    queue: Vec<(String, i64)>,
+    timestamp: i64,
+) -> Vec<TradeOutcome> {
+    let mut outcomes = Vec::new();
+    for (order_id, latest_price) in queue {
+        if let Some(outcome) = close_trade_at_price(
+            state,
+            &order_id,
+            latest_price,
+            "liquidated",
+            Some("cross_margin"),
+            false,
+            timestamp,
+        ) {
+            outcomes.push(outcome);
+        }
+    }
 
-        if let Some(user_balance) = state.balances.get_mut(&trade.user_id) {
-            *user_balance += pnl;
+    let has_open_positions = state.open_trades.values().any(|t| t.user_id == user_id);
+    if let Some(balance) = state.balances.get_mut(user_id) {
+        if *balance < 0 && !has_open_positions {
             println!(
-                "Liquidated order {} at price {} with PnL: {}. Updated balance: {}",
-                order_id, latest_price, pnl, user_balance
+                "Cross account {} liquidated with shortfall {}. Balance floored at 0",
+                user_id, -*balance
             );
+            state.ledger.insurance_fund += *balance;
+            *balance = 0;
+            if let Some(last) = outcomes.last_mut() {
+                last.updated_balance = Some(0);
+            }
         }
     }
+    outcomes
 }
 
 fn get_maintenance_margin_percent(margin_used: i64) -> i64 {
@@ -0,0 +1,506 @@
+use crate::kafka::producer;
+use crate::modules::liquidations::{
+    check_liquidation, liquidation_price, position_liquidation_price, unrealized_pnl,
+};
+use crate::modules::state::{EngineState, SharedEngineState};
+use crate::modules::types::{
+    LeverageChangeRequest, MarginAction, MarginMode, MarginModeRequest, PositionMarginRequest,
+    Side, Trade, TradeOutcome,
+};
+
+/// Switch a user's account between isolated and cross margin.
+/// Only allowed while the user has no open positions or resting orders.
+pub async fn process_margin_mode_change(state: SharedEngineState, req: MarginModeRequest) {
+    let mut engine_state = state.lock().await;
+
+    let has_open_positions = engine_state
+        .open_trades
+        .values()
+        .any(|trade| trade.user_id == req.user_id);
+    let has_resting_orders = engine_state.order_books.values().any(|book| {
+        book.buy
+            .values()
+            .chain(book.sell.values())
+            .flatten()
+            .any(|order| order.user_id == req.user_id)
+    });
+
+    let mut response_json = if has_open_positions || has_resting_orders {
+        println!(
+            "Rejected margin mode change for user {}: open positions or orders",
+            req.user_id
+        );
+        serde_json::json!({
+            "userId": req.user_id,
+            "status": "rejected",
+            "marginMode": engine_state.margin_mode(&req.user_id),
+            "reason": "Cannot change margin mode with open positions or orders"
+        })
+    } else {
+        engine_state
+            .margin_modes
+            .insert(req.user_id.clone(), req.margin_mode);
+        println!(
+            "Margin mode for user {} set to {:?}",
+            req.user_id, req.margin_mode
+        );
+        serde_json::json!({
+            "userId": req.user_id,
+            "status": "accepted",
+            "marginMode": req.margin_mode
+        })
+    };
+    if let Some(ref corr_id) = req.correlation_id {
+        response_json["correlationId"] = serde_json::json!(corr_id);
+    }
+    let response = response_json.to_string();
+    producer::send_margin_mode_response(&req.user_id, &response).await;
+}
+
+/// Initial margin a position must keep at `price`: its notional divided by the highest
+/// leverage its asset currently allows.
+pub fn initial_margin_requirement(engine_state: &EngineState, trade: &Trade, price: i64) -> i64 {
+    let notional = price as i128 * trade.quantity as i128 * trade.leverage as i128;
+    (notional / engine_state.max_leverage(&trade.asset).max(1) as i128) as i64
+}
+
+/// Move funds between the user's balance and the locked margin of an open isolated position.
+/// Removals must leave margin plus any unrealized loss above the initial margin requirement.
+pub async fn process_position_margin_change(
+    state: SharedEngineState,
+    req: PositionMarginRequest,
+    tx: tokio::sync::mpsc::Sender<String>,
+) {
+    let mut engine_state = state.lock().await;
+
+    let rejection = match engine_state.open_trades.get(&req.order_id) {
+        _ if req.amount <= 0 => Some("Amount must be positive"),
+        None => Some("Position not found"),
+        Some(trade) if trade.user_id != req.user_id => Some("Position not found"),
+        Some(_) if engine_state.margin_mode(&req.user_id) == MarginMode::Cross => {
+            Some("Margin adjustment is only available for isolated positions")
+        }
+        Some(trade) => {
+            let locked_margin = engine_state.get_locked_margin_or(&req.order_id, trade.margin);
+            match req.action {
+                MarginAction::Add => {
+                    let balance = engine_state
+                        .balances
+                        .get(&req.user_id)
+                        .copied()
+                        .unwrap_or(0);
+                    if balance < req.amount {
+                        Some("Insufficient balance")
+                    } else {
+                        None
+                    }
+                }
+                MarginAction::Remove => match engine_state.exit_price(trade) {
+                    None => Some("No price available for asset"),
+                    Some(price) => {
+                        let remaining = locked_margin - req.amount;
+                        let unrealized_loss = unrealized_pnl(trade, price).min(0);
+                        if remaining <= 0
+                            || remaining + unrealized_loss
+                                < initial_margin_requirement(&engine_state, trade, price)
+                        {
+                            Some("Removal would breach initial margin requirement")
+                        } else {
+                            None
+                        }
+                    }
+                },
+            }
+        }
+    };
+
+    if let Some(reason) = rejection {
+        println!(
+            "Rejected margin change for order {} by user {}: {}",
+            req.order_id, req.user_id, reason
+        );
+        let mut response_json = serde_json::json!({
+            "userId": req.user_id,
+            "orderId": req.order_id,
+            "status": "rejected",
+            "reason": reason
+        });
+        if let Some(ref corr_id) = req.correlation_id {
+            response_json["correlationId"] = serde_json::json!(corr_id);
+        }
+        let response = response_json.to_string();
+        producer::send_position_margin_response(&req.user_id, &response).await;
+        return;
+    }
+
+    let delta = match req.action {
+        MarginAction::Add => req.amount,
+        MarginAction::Remove => -req.amount,
+    };
+    if let Some(balance) = engine_state.balances.get_mut(&req.user_id) {
+        *balance -= delta;
+    }
+    let fallback_margin = engine_state.open_trades[&req.order_id].margin;
+    let new_margin = engine_state.get_locked_margin_or(&req.order_id, fallback_margin) + delta;
+    engine_state.set_locked_margin(&req.order_id, new_margin);
+    let trade = match engine_state.open_trades.get_mut(&req.order_id) {
+        Some(trade) => {
+            trade.margin = new_margin;
+            trade.clone()
+        }
+        None => return,
+    };
+    let liquidation_price = liquidation_price(&trade, new_margin);
+    let updated_balance = engine_state.balances.get(&req.user_id).copied();
+
+    println!(
+        "Margin for order {} changed by {}. New margin: {}, liquidation price: {:?}",
+        req.order_id, delta, new_margin, liquidation_price
+    );
+
+    let mut response_json = serde_json::json!({
+        "userId": req.user_id,
+        "orderId": req.order_id,
+        "status": "accepted",
+        "margin": new_margin,
+        "liquidationPrice": liquidation_price,
+        "updatedBalance": updated_balance
+    });
+    if let Some(ref corr_id) = req.correlation_id {
+        response_json["correlationId"] = serde_json::json!(corr_id);
+    }
+    let response = response_json.to_string();
+    producer::send_position_margin_response(&req.user_id, &response).await;
+
+    let reason = match req.action {
+        MarginAction::Add => "margin_added",
+        MarginAction::Remove => "margin_removed",
+    };
+    let trade_outcome = position_update_outcome(&engine_state, &trade, reason, req.timestamp);
+    if let Ok(json_string) = serde_json::to_string(&trade_outcome) {
+        let _ = tx.send(json_string).await;
+    }
+}
+
+/// Change the leverage of an open position without changing its exposure: quantity and
+/// margin are rescaled so quantity x leverage and margin x leverage stay constant. Lowering
+/// leverage locks more margin from the balance, raising it releases margin.
+pub async fn process_leverage_change(
+    state: SharedEngineState,
+    req: LeverageChangeRequest,
+    tx: tokio::sync::mpsc::Sender<String>,
+) {
+    let mut engine_state = state.lock().await;
+
+    let trade = match relever_position(&mut engine_state, &req) {
+        Ok(trade) => trade,
+        Err(reason) => {
+            println!(
+                "Rejected leverage change for order {} by user {}: {}",
+                req.order_id, req.user_id, reason
+            );
+            let mut response_json = serde_json::json!({
+                "userId": req.user_id,
+                "orderId": req.order_id,
+                "status": "rejected",
+                "reason": reason
+            });
+            if let Some(ref corr_id) = req.correlation_id {
+                response_json["correlationId"] = serde_json::json!(corr_id);
+            }
+            let response = response_json.to_string();
+            producer::send_position_leverage_response(&req.user_id, &response).await;
+            return;
+        }
+    };
+    let liquidation_price = position_liquidation_price(&engine_state, &trade, trade.margin);
+    let updated_balance = engine_state.balances.get(&req.user_id).copied();
+
+    println!(
+        "Leverage for order {} set to {}. New quantity: {}, new margin: {}, liquidation price: {:?}",
+        req.order_id, req.leverage, trade.quantity, trade.margin, liquidation_price
+    );
+
+    let mut response_json = serde_json::json!({
+        "userId": req.user_id,
+        "orderId": req.order_id,
+        "status": "accepted",
+        "leverage": req.leverage,
+        "quantity": trade.quantity,
+        "margin": trade.margin,
+        "liquidationPrice": liquidation_price,
+        "updatedBalance": updated_balance
+    });
+    if let Some(ref corr_id) = req.correlation_id {
+        response_json["correlationId"] = serde_json::json!(corr_id);
+    }
+    let response = response_json.to_string();
+    producer::send_position_leverage_response(&req.user_id, &response).await;
+
+    let trade_outcome =
+        position_update_outcome(&engine_state, &trade, "leverage_changed", req.timestamp);
+    if let Ok(json_string) = serde_json::to_string(&trade_outcome) {
+        let _ = tx.send(json_string).await;
+    }
+}
+
+/// Validate and apply a leverage change, returning the re-levered position or why it was
+/// rejected. Unrealized PnL is unchanged, since it scales with quantity x leverage.
+fn relever_position(
+    engine_state: &mut EngineState,
+    req: &LeverageChangeRequest,
+) -> Result<Trade, &'static str> {
+    let trade = match engine_state.open_trades.get(&req.order_id) {
+        Some(trade) if trade.user_id == req.user_id => trade,
+        _ => return Err("Position not found"),
+    };
+    if req.leverage < 1 || req.leverage > engine_state.max_leverage(&trade.asset) {
+        return Err("Leverage outside instrument limits");
+    }
+    if trade.leverage == req.leverage {
+        return Err("Leverage unchanged");
+    }
+    let exposure = trade.quantity as i128 * trade.leverage as i128;
+    if exposure % req.leverage as i128 != 0 {
+        return Err("Leverage would leave a fractional quantity");
+    }
+    let price = engine_state
+        .exit_price(trade)
+        .ok_or("No price available for asset")?;
+
+    let locked_margin = engine_state.get_locked_margin_or(&req.order_id, trade.margin);
+    // Round up so the re-levered position is never under-margined
+    let scaled = locked_margin as i128 * trade.leverage as i128;
+    let new_margin = ((scaled + req.leverage as i128 - 1) / req.leverage as i128) as i64;
+    let additional_margin = new_margin - locked_margin;
+
+    let mut relevered = trade.clone();
+    relevered.leverage = req.leverage;
+    relevered.quantity = (exposure / req.leverage as i128) as i64;
+    relevered.margin = new_margin;
+    let quantity_change = relevered.quantity - trade.quantity;
+    let unrealized_loss = unrealized_pnl(&relevered, price).min(0);
+    let balance = engine_state
+        .balances
+        .get(&req.user_id)
+        .copied()
+        .unwrap_or(0);
+    if additional_margin > balance {
+        return Err("Insufficient balance");
+    }
+    if new_margin + unrealized_loss < initial_margin_requirement(engine_state, &relevered, price)
+        || check_liquidation(&relevered, price)
+    {
+        return Err("New leverage would breach initial margin requirement");
+    }
+
+    if let Some(balance) = engine_state.balances.get_mut(&req.user_id) {
+        *balance -= additional_margin;
+    }
+    engine_state.set_locked_margin(&req.order_id, new_margin);
+    // The holdings ledger tracks position quantity, so it follows the rescale
+    let holdings_key = (req.user_id.clone(), relevered.asset.clone());
+    if let Some(holdings) = engine_state.holdings.get_mut(&holdings_key) {
+        match relevered.side {
+            Side::Buy => *holdings += quantity_change,
+            Side::Sell => *holdings -= quantity_change,
+        }
+    }
+    engine_state
+        .open_trades
+        .insert(req.order_id.clone(), relevered.clone());
+    Ok(relevered)
+}
+
+/// Outcome for a margin or leverage change on a still-open position, so the persisted
+/// trade row and balance stay in sync.
+pub fn position_update_outcome(
+    engine_state: &EngineState,
+    trade: &Trade,
+    reason: &str,
+    timestamp: i64,
+) -> TradeOutcome {
+    let locked_margin = engine_state.get_locked_margin_or(&trade.id, trade.margin);
+    TradeOutcome {
+        trade_id: trade.id.clone(),
+        user_id: trade.user_id.clone(),
+        asset: trade.asset.clone(),
+        side: trade.side.clone(),
+        quantity: trade.quantity,
+        entry_price: trade.entry_price,
+        close_price: trade.close_price,
+        pnl: Some(0),
+        status: Some("filled".to_string()),
+        timestamp: Some(timestamp),
+        margin: Some(locked_margin),
+        leverage: Some(trade.leverage),
+        slippage: Some(0),
+        reason: Some(reason.to_string()),
+        success: Some(true),
+        order_type: trade.order_type.clone(),
+        limit_price: trade.limit_price,
+        updated_balance: engine_state.balances.get(&trade.user_id).copied(),
+        updated_holdings: engine_state
+            .holdings
+            .get(&(trade.user_id.clone(), trade.asset.clone()))
+            .copied(),
+        locked_margin: Some(locked_margin),
+        liquidation_price: position_liquidation_price(engine_state, trade, locked_margin),
+        fee: None,
+        term: trade.term,
+        resting_quantity: None,
+        resting_margin: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::CONFIG;
+    use crate::modules::liquidations::open_position_liquidation_price;
+    use crate::modules::types::{AssetInfo, AssetStatus};
+
+    fn state_with_position(quantity: i64, leverage: i64, margin: i64) -> EngineState {
+        let mut engine_state = EngineState::new();
+        engine_state.balances.insert("user".to_string(), 1_000);
+        engine_state.prices.insert("BTC".to_string(), 100);
+        engine_state
+            .holdings
+            .insert(("user".to_string(), "BTC".to_string()), quantity);
+        let trade = Trade {
+            id: "order".to_string(),
+            user_id: "user".to_string(),
+            asset: "BTC".to_string(),
+            side: Side::Buy,
+            margin,
+            leverage,
+            quantity,
+            entry_price: Some(100),
+            close_price: None,
+            pnl: None,
+            status: None,
+            created_at: Some(0),
+            closed_at: None,
+            take_profit_percent: None,
+            stop_loss_percent: None,
+            price: Some(100),
+            term: None,
+            order_type: None,
+            limit_price: None,
+        };
+        engine_state.set_locked_margin("order", margin);
+        engine_state.open_trades.insert("order".to_string(), trade);
+        engine_state
+    }
+
+    fn leverage_request(leverage: i64) -> LeverageChangeRequest {
+        LeverageChangeRequest {
+            user_id: "user".to_string(),
+            correlation_id: None,
+            order_id: "order".to_string(),
+            leverage,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn raising_leverage_keeps_exposure_and_releases_margin() {
+        let mut engine_state = state_with_position(4, 10, 400);
+        let before = engine_state.open_trades["order"].clone();
+
+        let trade = relever_position(&mut engine_state, &leverage_request(20)).unwrap();
+
+        assert_eq!(trade.quantity, 2);
+        assert_eq!(trade.margin, 200);
+        assert_eq!(
+            trade.quantity * trade.leverage,
+            before.quantity * before.leverage
+        );
+        assert_eq!(engine_state.get_locked_margin_or("order", 0), 200);
+        assert_eq!(engine_state.balances["user"], 1_200);
+        assert_eq!(
+            engine_state.holdings[&("user".to_string(), "BTC".to_string())],
+            2
+        );
+        assert_eq!(unrealized_pnl(&trade, 110), unrealized_pnl(&before, 110));
+    }
+
+    #[test]
+    fn lowering_leverage_keeps_exposure_and_locks_margin() {
+        let mut engine_state = state_with_position(4, 10, 400);
+        let before = engine_state.open_trades["order"].clone();
+
+        let trade = relever_position(&mut engine_state, &leverage_request(5)).unwrap();
+
+        assert_eq!(trade.quantity, 8);
+        assert_eq!(trade.margin, 800);
+        assert_eq!(engine_state.get_locked_margin_or("order", 0), 800);
+        assert_eq!(engine_state.balances["user"], 600);
+        assert_eq!(
+            engine_state.holdings[&("user".to_string(), "BTC".to_string())],
+            8
+        );
+        assert_eq!(unrealized_pnl(&trade, 90), unrealized_pnl(&before, 90));
+        assert_eq!(engine_state.open_trades["order"].quantity, 8);
+    }
+
+    #[test]
+    fn lowering_leverage_without_balance_is_rejected() {
+        let mut engine_state = state_with_position(4, 10, 400);
+        engine_state.balances.insert("user".to_string(), 100);
+
+        let result = relever_position(&mut engine_state, &leverage_request(5));
+
+        assert_eq!(result.unwrap_err(), "Insufficient balance");
+        assert_eq!(engine_state.open_trades["order"].leverage, 10);
+        assert_eq!(engine_state.balances["user"], 100);
+    }
+
+    #[test]
+    fn leverage_leaving_a_fractional_quantity_is_rejected() {
+        let mut engine_state = state_with_position(1, 10, 100);
+
+        let result = relever_position(&mut engine_state, &leverage_request(20));
+
+        assert_eq!(
+            result.unwrap_err(),
+            "Leverage would leave a fractional quantity"
+        );
+    }
+
+    #[test]
+    fn initial_margin_follows_the_asset_leverage_cap() {
+        let mut engine_state = state_with_position(4, 10, 400);
+        let trade = engine_state.open_trades["order"].clone();
+        let engine_wide = initial_margin_requirement(&engine_state, &trade, 100);
+        assert_eq!(engine_wide, 4_000 / CONFIG.max_leverage);
+
+        engine_state.assets.insert(
+            "BTC".to_string(),
+            AssetInfo {
+                status: AssetStatus::Trading,
+                max_leverage: Some(10),
+            },
+        );
+        assert_eq!(initial_margin_requirement(&engine_state, &trade, 100), 400);
+    }
+
+    #[test]
+    fn only_isolated_positions_publish_a_liquidation_price() {
+        let mut engine_state = state_with_position(4, 10, 400);
+        let trade = engine_state.open_trades["order"].clone();
+        let isolated = position_update_outcome(&engine_state, &trade, "margin_added", 0);
+        assert_eq!(isolated.liquidation_price, liquidation_price(&trade, 400));
+        assert!(isolated.liquidation_price.is_some());
+
+        engine_state
+            .margin_modes
+            .insert("user".to_string(), MarginMode::Cross);
+        let cross = position_update_outcome(&engine_state, &trade, "leverage_changed", 0);
+        assert_eq!(cross.liquidation_price, None);
+        assert_eq!(
+            open_position_liquidation_price(&engine_state, "order"),
+            None
+        );
+    }
+}
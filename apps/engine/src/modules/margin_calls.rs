@@ -0,0 +1,148 @@
+use crate::modules::config::CONFIG;
+use crate::modules::liquidations::{
+    account_equity, account_maintenance_margin, maintenance_margin, unrealized_pnl,
+};
+use crate::modules::state::EngineState;
+use crate::modules::types::{MarginCallEvent, MarginMode};
+use std::collections::HashSet;
+
+/// Re-evaluate margin levels of isolated positions and cross accounts against the configured
+/// margin call thresholds. Returns an event for every position/account whose breached level
+/// changed; a breached level only clears once the margin level recovers past the hysteresis band.
+pub fn evaluate_margin_calls(engine_state: &mut EngineState, now: i64) -> Vec<MarginCallEvent> {
+    let mut events = Vec::new();
+    let mut watched = HashSet::new();
+    let mut cross_users = HashSet::new();
+
+    let mut positions = Vec::new();
+    for (order_id, trade) in engine_state.open_trades.iter() {
+        if engine_state.margin_mode(&trade.user_id) == MarginMode::Cross {
+            cross_users.insert(trade.user_id.clone());
+            continue;
+        }
+        if engine_state.is_market_halted(&trade.asset) {
+            continue;
+        }
+        if let Some(price) = engine_state.exit_price(trade) {
+            let margin = engine_state.get_locked_margin_or(order_id, trade.margin);
+            positions.push((
+                order_id.clone(),
+                trade.user_id.clone(),
+                trade.asset.clone(),
+                margin + unrealized_pnl(trade, price),
+                maintenance_margin(margin),
+            ));
+        }
+    }
+
+    for (order_id, user_id, asset, margin, maintenance) in positions {
+        watched.insert(order_id.clone());
+        if let Some((status, threshold, level)) =
+            update_level(engine_state, &order_id, margin, maintenance)
+        {
+            events.push(MarginCallEvent {
+                scope: "position".to_string(),
+                user_id,
+                trade_id: Some(order_id),
+                asset: Some(asset),
+                status,
+                threshold_percent: threshold,
+                margin_level_percent: level,
+                margin,
+                maintenance_margin: maintenance,
+                timestamp: now,
+            });
+        }
+    }
+
+    for user_id in cross_users {
+        watched.insert(user_id.clone());
+        // Account equity is unreliable while any of its markets is halted
+        let has_halted_market = engine_state
+            .open_trades
+            .values()
+            .any(|trade| trade.user_id == user_id && engine_state.is_market_halted(&trade.asset));
+        if has_halted_market {
+            continue;
+        }
+        let equity = account_equity(engine_state, &user_id);
+        let maintenance = account_maintenance_margin(engine_state, &user_id);
+        if let Some((status, threshold, level)) =
+            update_level(engine_state, &user_id, equity, maintenance)
+        {
+            events.push(MarginCallEvent {
+                scope: "account".to_string(),
+                user_id,
+                trade_id: None,
+                asset: None,
+                status,
+                threshold_percent: threshold,
+                margin_level_percent: level,
+                margin: equity,
+                maintenance_margin: maintenance,
+                timestamp: now,
+            });
+        }
+    }
+
+    // Positions and accounts that were closed or liquidated no longer need a level
+    engine_state
+        .margin_calls
+        .retain(|key, _| watched.contains(key));
+
+    events
+}
+
+/// Update the breached level count for `key`, returning (status, threshold, margin level %)
+/// when it changed.
+fn update_level(
+    engine_state: &mut EngineState,
+    key: &str,
+    margin: i64,
+    maintenance: i64,
+) -> Option<(String, i64, i64)> {
+    if maintenance <= 0 {
+        return None;
+    }
+    let level = margin * 100 / maintenance;
+    let previous = engine_state.margin_calls.get(key).copied().unwrap_or(0);
+
+    let breached = CONFIG
+        .margin_call_levels
+        .iter()
+        .enumerate()
+        .take_while(|(index, threshold)| {
+            let clear_at = if *index < previous {
+                **threshold + CONFIG.margin_call_hysteresis
+            } else {
+                **threshold
+            };
+            level < clear_at
+        })
+        .count();
+
+    if breached == previous {
+        return None;
+    }
+    if breached == 0 {
+        engine_state.margin_calls.remove(key);
+    } else {
+        engine_state.margin_calls.insert(key.to_string(), breached);
+    }
+
+    if breached > previous {
+        let threshold = CONFIG.margin_call_levels[breached - 1];
+        println!(
+            "Margin call triggered for {}: margin level {}% below {}%",
+            key, level, threshold
+        );
+        Some(("triggered".to_string(), threshold, level))
+    } else {
+        let threshold = CONFIG.margin_call_levels[breached];
+        println!(
+            "Margin call cleared for {}: margin level {}% back above {}%",
+            key, level, threshold
+        );
+        Some(("cleared".to_string(), threshold, level))
+    }
+}
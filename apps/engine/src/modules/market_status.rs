@@ -0,0 +1,93 @@
+use crate::kafka::producer;
+use crate::modules::config::CONFIG;
+use crate::modules::state::{EngineState, SharedEngineState};
+use crate::modules::types::{AssetStatus, MarketStatusEvent};
+
+/// Record an accepted tick for `asset` dated `tick_at`, resuming its market if the feed had
+/// gone stale. A tick dated ahead of `now` counts as current.
+pub fn record_price_tick(
+    engine_state: &mut EngineState,
+    asset: &str,
+    tick_at: i64,
+    now: i64,
+) -> Option<MarketStatusEvent> {
+    let tick_at = tick_at.min(now);
+    let updated_at = engine_state
+        .price_updated_at
+        .entry(asset.to_string())
+        .or_insert(tick_at);
+    *updated_at = (*updated_at).max(tick_at);
+    let updated_at = *updated_at;
+    // A late tick doesn't revive a feed that is still stale by its own timestamps
+    if now - updated_at > CONFIG.price_max_age_ms || !engine_state.stale_markets.remove(asset) {
+        return None;
+    }
+    println!("Price feed for {} recovered. Trading resumed", asset);
+    Some(MarketStatusEvent {
+        asset: asset.to_string(),
+        status: "trading".to_string(),
+        reason: "price_resumed".to_string(),
+        last_price_at: Some(updated_at),
+        timestamp: now,
+    })
+}
+
+/// Halt every trading asset whose last accepted tick, by the tick's own timestamp, is older
+/// than the configured max age, or that has never ticked.
+pub fn detect_stale_markets(engine_state: &mut EngineState, now: i64) -> Vec<MarketStatusEvent> {
+    let mut newly_stale: Vec<(String, Option<i64>)> = engine_state
+        .assets
+        .iter()
+        .filter(|(asset, info)| {
+            info.status != AssetStatus::Delisted && !engine_state.stale_markets.contains(*asset)
+        })
+        .map(|(asset, _)| {
+            (
+                asset.clone(),
+                engine_state.price_updated_at.get(asset).copied(),
+            )
+        })
+        .filter(|(_, updated_at)| {
+            updated_at.is_none_or(|updated_at| now - updated_at > CONFIG.price_max_age_ms)
+        })
+        .collect();
+    newly_stale.sort();
+
+    newly_stale
+        .into_iter()
+        .map(|(asset, updated_at)| {
+            match updated_at {
+                Some(updated_at) => println!(
+                    "Price feed for {} stale for {} ms. Trading halted",
+                    asset,
+                    now - updated_at
+                ),
+                None => println!("No price received for {}. Trading halted", asset),
+            }
+            engine_state.stale_markets.insert(asset.clone());
+            MarketStatusEvent {
+                asset,
+                status: "halted".to_string(),
+                reason: "stale_price".to_string(),
+                last_price_at: updated_at,
+                timestamp: now,
+            }
+        })
+        .collect()
+}
+
+/// Run the staleness check against shared state and publish any halts.
+pub async fn monitor_price_staleness(state: SharedEngineState, now: i64) -> bool {
+    let mut engine_state = state.lock().await;
+    let events = detect_stale_markets(&mut engine_state, now);
+    for event in &events {
+        publish_market_status(event).await;
+    }
+    !events.is_empty()
+}
+
+pub async fn publish_market_status(event: &MarketStatusEvent) {
+    if let Ok(json_string) = serde_json::to_string(event) {
+        producer::publish_market_status(&event.asset, &json_string).await;
+    }
+}
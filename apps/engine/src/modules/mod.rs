@@ -1,10 +1,32 @@
+pub mod account_loads;
+pub mod assets;
+pub mod candles;
+pub mod closing;
+pub mod config;
+pub mod dedup;
 pub mod execution;
+pub mod financing;
+pub mod halts;
+pub mod invariants;
+pub mod journal;
 pub mod liquidations;
+pub mod margin;
+pub mod margin_calls;
+pub mod market_status;
 pub mod netting;
 pub mod order_matching;
 pub mod pnl;
+pub mod price_aggregator;
+pub mod price_filter;
+pub mod price_store;
 pub mod price_updater;
 pub mod processor;
+pub mod reconciliation;
+pub mod sequencer;
+pub mod settlement;
+pub mod snapshot;
 pub mod state;
+pub mod state_load;
 pub mod stop_loss_take_profit;
+pub mod terms;
 pub mod types;
@@ -1,4 +1,5 @@
 use crate::modules::execution::publish_trade_outcome_for_market_order;
+use crate::modules::margin::position_update_outcome;
 use crate::modules::pnl::calculate_pnl;
 use crate::modules::state::EngineState;
 use crate::modules::types::{order_to_trade, Order, Side};
@@ -47,8 +48,8 @@ pub async fn apply_netting(
         };
 
         // Update balance with PnL and return margin
+        engine_state.realize_pnl(&order.user_id, pnl);
         if let Some(balance) = engine_state.balances.get_mut(&order.user_id) {
-            *balance += pnl;
             *balance += margin_return;
         }
 
@@ -97,6 +98,18 @@ pub async fn apply_netting(
                 engine_state.release_locked_margin(&existing_id);
             } else {
                 engine_state.set_locked_margin(&existing_id, new_margin);
+                // The rest of the position stays open with less margin; republish it so its
+                // quantity, margin and liquidation price stay current
+                let remainder = engine_state.open_trades[&existing_id].clone();
+                let trade_outcome = position_update_outcome(
+                    engine_state,
+                    &remainder,
+                    "partially_closed",
+                    order.created_at,
+                );
+                if let Ok(json_string) = serde_json::to_string(&trade_outcome) {
+                    let _ = tx.send(json_string).await;
+                }
             }
         }
 
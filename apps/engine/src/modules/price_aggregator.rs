@@ -0,0 +1,212 @@
+use crate::kafka::producer;
+use crate::modules::config::CONFIG;
+use crate::modules::sequencer::MarketView;
+use crate::modules::state::EngineState;
+use crate::modules::types::{
+    AggregatedPrice, PriceQueryRequest, PriceUpdate, Quote, SourceQuote, StagedTick,
+};
+use std::sync::Arc;
+
+/// Median of one field of the quotes; the mean of the middle two for an even count.
+fn median(quotes: &[SourceQuote], field: fn(&SourceQuote) -> i64) -> i64 {
+    let mut prices: Vec<i64> = quotes.iter().map(field).collect();
+    prices.sort_unstable();
+    let mid = prices.len() / 2;
+    if prices.len().is_multiple_of(2) {
+        (prices[mid - 1] + prices[mid]) / 2
+    } else {
+        prices[mid]
+    }
+}
+
+fn fresh_quotes<'a>(quotes: impl Iterator<Item = &'a SourceQuote>, now: i64) -> Vec<SourceQuote> {
+    quotes
+        .filter(|quote| now - quote.received_at <= CONFIG.price_source_max_age_ms)
+        .cloned()
+        .collect()
+}
+
+/// Record an accepted tick as its source's latest quote and recompute the asset's bid, ask and
+/// mid as medians of all fresh quotes. Stale sources drop out of the median automatically;
+/// returns None while fewer than `price_quorum` sources are fresh, leaving the previous quote in place.
+pub fn aggregate_price(
+    engine_state: &mut EngineState,
+    update: &PriceUpdate,
+    now: i64,
+) -> Option<Quote> {
+    let quotes = engine_state
+        .price_sources
+        .entry(update.asset.clone())
+        .or_default();
+    quotes.insert(
+        update.source.clone(),
+        SourceQuote {
+            source: update.source.clone(),
+            price: update.price,
+            bid: update.bid,
+            ask: update.ask,
+            timestamp: update.timestamp,
+            received_at: now,
+        },
+    );
+    let fresh = fresh_quotes(quotes.values(), now);
+
+    if fresh.len() < CONFIG.price_quorum {
+        println!(
+            "Price quorum not met for {}: {} of {} sources fresh",
+            update.asset,
+            fresh.len(),
+            CONFIG.price_quorum
+        );
+        return None;
+    }
+
+    // Copies the series only while the published market view still shares it
+    let history = Arc::make_mut(
+        engine_state
+            .price_history
+            .entry(update.asset.clone())
+            .or_default(),
+    );
+    if let Some(previous) = history.back() {
+        for quote in &previous.sources {
+            if !fresh.iter().any(|fresh| fresh.source == quote.source) {
+                println!(
+                    "Price source {} stale for {}. Failing over to remaining sources",
+                    quote.source, update.asset
+                );
+            }
+        }
+    }
+
+    let mid = median(&fresh, |quote| quote.price);
+    let mut aggregated = Quote {
+        bid: median(&fresh, |quote| quote.bid),
+        ask: median(&fresh, |quote| quote.ask),
+        mid,
+    };
+    // Medians taken per side can cross when sources disagree; fall back to the mid
+    if aggregated.bid > aggregated.ask {
+        aggregated.bid = mid;
+        aggregated.ask = mid;
+    }
+    // The latest price always stands; it replaces one kept less than the spacing after the one before
+    let len = history.len();
+    if len >= 2
+        && history[len - 1].timestamp - history[len - 2].timestamp < CONFIG.price_history_spacing_ms
+    {
+        history.pop_back();
+    }
+    history.push_back(AggregatedPrice {
+        asset: update.asset.clone(),
+        price: aggregated.mid,
+        bid: aggregated.bid,
+        ask: aggregated.ask,
+        sources: fresh,
+        timestamp: now,
+    });
+    while history
+        .front()
+        .is_some_and(|oldest| now - oldest.timestamp > CONFIG.price_history_ms)
+    {
+        history.pop_front();
+    }
+    Some(aggregated)
+}
+
+/// Range the aggregated mid spanned over the ticks coalesced into `tick`: the median of the
+/// fresh sources with `tick`'s source moved to the low and to the high of what it sent. The
+/// median never moves further than the source did, so this never exceeds its raw range.
+pub fn aggregated_range(engine_state: &EngineState, tick: &StagedTick, now: i64) -> (i64, i64) {
+    let update = &tick.update;
+    let Some(quotes) = engine_state.price_sources.get(&update.asset) else {
+        return (update.price, update.price);
+    };
+    let mut fresh = fresh_quotes(quotes.values(), now);
+    let mut mid_with = |price: i64| {
+        for quote in fresh
+            .iter_mut()
+            .filter(|quote| quote.source == update.source)
+        {
+            quote.price = price;
+        }
+        median(&fresh, |quote| quote.price)
+    };
+    let low = mid_with(tick.low);
+    let high = mid_with(tick.high);
+    (low, high)
+}
+
+/// Answer a price query with the aggregated price in effect at the requested time (or the latest)
+/// together with the source quotes it was derived from.
+pub async fn process_price_query(market: &MarketView, req: PriceQueryRequest) {
+    let found = market
+        .price_history
+        .get(&req.asset)
+        .and_then(|history| match req.at {
+            Some(at) => history.iter().rev().find(|entry| entry.timestamp <= at),
+            None => history.back(),
+        });
+    let mut response_json = match found {
+        Some(entry) => serde_json::json!({
+            "asset": req.asset,
+            "status": "found",
+            "price": entry
+        }),
+        None => serde_json::json!({
+            "asset": req.asset,
+            "status": "not_found",
+            "reason": "No aggregated price recorded for the requested time"
+        }),
+    };
+    if let Some(ref corr_id) = req.correlation_id {
+        response_json["correlationId"] = serde_json::json!(corr_id);
+    }
+    let response = response_json.to_string();
+    producer::send_price_query_response(&req.asset, &response).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(source: &str, price: i64) -> PriceUpdate {
+        PriceUpdate {
+            asset: "BTC".to_string(),
+            price,
+            bid: price,
+            ask: price,
+            timestamp: 0,
+            source: source.to_string(),
+        }
+    }
+
+    #[test]
+    fn coalesced_range_is_taken_from_the_aggregated_price() {
+        let mut engine_state = EngineState::new();
+        for (source, price) in [("a", 100), ("b", 110), ("c", 120)] {
+            aggregate_price(&mut engine_state, &update(source, price), 0);
+        }
+        // Source c swung from 90 to 130 between sequencer runs; the median only moved 100..110
+        let tick = StagedTick {
+            update: update("c", 120),
+            high: 130,
+            low: 90,
+        };
+        assert_eq!(aggregated_range(&engine_state, &tick, 0), (100, 110));
+    }
+
+    #[test]
+    fn history_is_kept_by_time_and_thinned_to_its_spacing() {
+        let mut engine_state = EngineState::new();
+        let spacing = CONFIG.price_history_spacing_ms;
+        for now in [0, 1, 2, spacing + 2, CONFIG.price_history_ms + 1] {
+            aggregate_price(&mut engine_state, &update("a", 100), now);
+        }
+        let kept: Vec<i64> = engine_state.price_history["BTC"]
+            .iter()
+            .map(|entry| entry.timestamp)
+            .collect();
+        assert_eq!(kept, vec![spacing + 2, CONFIG.price_history_ms + 1]);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::modules::config::CONFIG;
+use crate::modules::types::{PriceRejections, PriceUpdate, QuarantinedPrice};
+use std::collections::HashMap;
+
+/// Why a price tick was dropped before reaching `prices`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceRejection {
+    OutOfOrder,  // older than the last accepted tick
+    NonPositive, // zero or negative price
+    Crossed,     // bid above ask
+    Quarantined, // outside the deviation band and not yet confirmed
+}
+
+/// Whether `price` lies within the asset's deviation band around `reference`.
+fn within_band(asset: &str, reference: i64, price: i64) -> bool {
+    let max_move = reference as i128 * CONFIG.price_deviation_bps(asset) as i128 / 10_000;
+    ((price - reference) as i128).abs() <= max_move
+}
+
+/// Feed filter state, kept by the price store so every tick is checked as it arrives rather
+/// than only the ones left once queued ticks are coalesced.
+#[derive(Default)]
+pub struct PriceFilter {
+    last_tick_at: HashMap<(String, String), i64>, // (asset, source) -> feed timestamp of the last accepted tick
+    last_price: HashMap<String, i64>,             // asset -> last accepted price from any source
+    quarantined: HashMap<String, HashMap<String, QuarantinedPrice>>, // asset -> source -> outlier awaiting confirmation
+    rejections: HashMap<String, PriceRejections>,                    // asset -> dropped tick counts
+}
+
+impl PriceFilter {
+    /// Resume from a source's last accepted tick, so older ticks stay out of order.
+    pub fn seed_source(&mut self, asset: &str, source: &str, tick_at: i64, price: i64) {
+        self.last_tick_at
+            .insert((asset.to_string(), source.to_string()), tick_at);
+        self.last_price.insert(asset.to_string(), price);
+    }
+
+    /// Validate a source's tick against that source's last accepted tick and `reference`, the
+    /// asset's current mid price (the last accepted tick stands in until there is one).
+    /// Returns why the tick was dropped, if it was (counted per asset). A move beyond the
+    /// deviation band is only accepted once `price_confirmation_ticks` consecutive ticks from
+    /// the same source agree on the new level.
+    pub fn check(
+        &mut self,
+        update: &PriceUpdate,
+        reference: Option<i64>,
+    ) -> Result<(), PriceRejection> {
+        let verdict = self.check_update(update, reference);
+        match verdict {
+            Ok(()) => {
+                self.last_tick_at.insert(
+                    (update.asset.clone(), update.source.clone()),
+                    update.timestamp,
+                );
+                self.last_price.insert(update.asset.clone(), update.price);
+            }
+            Err(rejection) => {
+                let counts = self.rejections.entry(update.asset.clone()).or_default();
+                match rejection {
+                    PriceRejection::OutOfOrder => counts.out_of_order += 1,
+                    PriceRejection::NonPositive => counts.non_positive += 1,
+                    PriceRejection::Crossed => counts.crossed += 1,
+                    PriceRejection::Quarantined => counts.quarantined += 1,
+                }
+                println!(
+                    "Rejected {} price {} from {} at {}: {:?} (rejections so far: {:?})",
+                    update.asset, update.price, update.source, update.timestamp, rejection, counts
+                );
+            }
+        }
+        verdict
+    }
+
+    fn check_update(
+        &mut self,
+        update: &PriceUpdate,
+        reference: Option<i64>,
+    ) -> Result<(), PriceRejection> {
+        let asset = update.asset.as_str();
+        let last_tick_at = self
+            .last_tick_at
+            .get(&(update.asset.clone(), update.source.clone()));
+        if last_tick_at.is_some_and(|last| update.timestamp < *last) {
+            return Err(PriceRejection::OutOfOrder);
+        }
+        if update.price <= 0 || update.bid <= 0 || update.ask <= 0 {
+            return Err(PriceRejection::NonPositive);
+        }
+        if update.bid > update.ask {
+            return Err(PriceRejection::Crossed);
+        }
+
+        let reference = match reference.or_else(|| self.last_price.get(asset).copied()) {
+            Some(price) => price,
+            // First tick for the asset sets the reference
+            None => {
+                self.accept(update);
+                return Ok(());
+            }
+        };
+        if within_band(asset, reference, update.price) {
+            self.accept(update);
+            return Ok(());
+        }
+
+        // Outlier: count it towards confirming a new level if it agrees with the pending one
+        let pending = self
+            .quarantined
+            .get(asset)
+            .and_then(|pending| pending.get(&update.source));
+        let confirmations = match pending {
+            Some(pending) if within_band(asset, pending.price, update.price) => {
+                pending.confirmations + 1
+            }
+            _ => 1,
+        };
+        if confirmations >= CONFIG.price_confirmation_ticks {
+            println!(
+                "Confirmed {} move from {} to {} after {} ticks",
+                asset, reference, update.price, confirmations
+            );
+            self.accept(update);
+            return Ok(());
+        }
+        self.quarantined
+            .entry(asset.to_string())
+            .or_default()
+            .insert(
+                update.source.clone(),
+                QuarantinedPrice {
+                    price: update.price,
+                    confirmations,
+                },
+            );
+        Err(PriceRejection::Quarantined)
+    }
+
+    fn accept(&mut self, update: &PriceUpdate) {
+        if let Some(pending) = self.quarantined.get_mut(&update.asset) {
+            pending.remove(&update.source);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::modules::price_filter::PriceFilter;
+use crate::modules::state::EngineState;
+use crate::modules::types::{PriceUpdate, Quote, StagedTick};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// Source assumed for ticks published without one.
+pub const DEFAULT_PRICE_SOURCE: &str = "backpack";
+
+/// An asset's latest accepted quote and when it was accepted.
+#[derive(Debug, Clone, Copy)]
+pub struct PricePoint {
+    pub quote: Quote,
+    pub updated_at: i64,
+}
+
+/// Ticks filtered on arrival and waiting for the sequencer.
+#[derive(Default)]
+struct Intake {
+    filter: PriceFilter,
+    staged: HashMap<(String, String), StagedTick>, // (asset, source) -> newest tick not yet applied
+}
+
+/// Prices kept apart from `EngineState`: the price consumer parses, filters and stages ticks
+/// here and readers get quotes from here, so neither waits on the state lock while orders are
+/// matched.
+pub struct PriceStore {
+    intake: Mutex<Intake>,
+    quotes: watch::Sender<Arc<HashMap<String, PricePoint>>>, // asset -> latest accepted quote
+}
+
+pub static PRICE_STORE: Lazy<PriceStore> = Lazy::new(|| PriceStore {
+    intake: Mutex::new(Intake::default()),
+    quotes: watch::channel(Arc::new(HashMap::new())).0,
+});
+
+/// Parse a raw "price-updates" payload. Mid-only payloads quote bid = ask = mid; ticks without
+/// a timestamp are taken as sent at `now`.
+pub fn parse_tick(payload: &str, now: i64) -> Option<PriceUpdate> {
+    let tick = serde_json::from_str::<Value>(payload).ok()?;
+    let asset = tick["asset"].as_str()?;
+    let bid = scaled_field(&tick["bid"]);
+    let ask = scaled_field(&tick["ask"]);
+    let price = scaled_field(&tick["price"]).or(match (bid, ask) {
+        (Some(bid), Some(ask)) => Some((bid + ask) / 2),
+        _ => None,
+    })?;
+    Some(PriceUpdate {
+        asset: asset.to_string(),
+        price,
+        bid: bid.unwrap_or(price),
+        ask: ask.unwrap_or(price),
+        timestamp: tick["timestamp"].as_i64().unwrap_or(now),
+        source: tick["source"]
+            .as_str()
+            .unwrap_or(DEFAULT_PRICE_SOURCE)
+            .to_string(),
+    })
+}
+
+/// A scaled integer price sent either as a number or as a numeric string.
+fn scaled_field(value: &Value) -> Option<i64> {
+    value
+        .as_i64()
+        .or_else(|| value.as_str().and_then(|raw| raw.parse::<i64>().ok()))
+}
+
+impl PriceStore {
+    /// Filter `update` and, if accepted, keep it as the newest tick from its source, folding in
+    /// any the sequencer hasn't taken yet. Returns true when nothing was waiting, so the
+    /// sequencer must be told.
+    pub fn stage_tick(&self, update: PriceUpdate) -> bool {
+        let reference = self.quote(&update.asset).map(|quote| quote.mid);
+        let mut intake = self.intake.lock().unwrap_or_else(|e| e.into_inner());
+        if intake.filter.check(&update, reference).is_err() {
+            return false;
+        }
+        let key = (update.asset.clone(), update.source.clone());
+        match intake.staged.get_mut(&key) {
+            Some(staged) => {
+                staged.high = staged.high.max(update.price);
+                staged.low = staged.low.min(update.price);
+                staged.update = update;
+                false
+            }
+            None => {
+                let staged = StagedTick {
+                    high: update.price,
+                    low: update.price,
+                    update,
+                };
+                intake.staged.insert(key, staged);
+                true
+            }
+        }
+    }
+
+    /// The tick staged for `asset` from `source`, if it hasn't been taken yet.
+    pub fn take_tick(&self, asset: &str, source: &str) -> Option<StagedTick> {
+        let mut intake = self.intake.lock().unwrap_or_else(|e| e.into_inner());
+        intake
+            .staged
+            .remove(&(asset.to_string(), source.to_string()))
+    }
+
+    pub fn publish_quote(&self, asset: &str, point: PricePoint) {
+        self.quotes.send_modify(|quotes| {
+            Arc::make_mut(quotes).insert(asset.to_string(), point);
+        });
+    }
+
+    /// Publish the quotes a restored state already holds, and resume filtering from each
+    /// source's last accepted tick.
+    pub fn seed(&self, engine_state: &EngineState) {
+        let quotes = engine_state
+            .quotes
+            .iter()
+            .map(|(asset, quote)| {
+                let point = PricePoint {
+                    quote: *quote,
+                    updated_at: engine_state
+                        .price_updated_at
+                        .get(asset)
+                        .copied()
+                        .unwrap_or(0),
+                };
+                (asset.clone(), point)
+            })
+            .collect();
+        self.quotes.send_replace(Arc::new(quotes));
+
+        let mut intake = self.intake.lock().unwrap_or_else(|e| e.into_inner());
+        for (asset, sources) in &engine_state.price_sources {
+            for quote in sources.values() {
+                intake
+                    .filter
+                    .seed_source(asset, &quote.source, quote.timestamp, quote.price);
+            }
+        }
+    }
+
+    /// Latest accepted quote for `asset`. The sequencer publishes quotes as it applies ticks,
+    /// so between events this is the quote state was last updated with.
+    pub fn quote(&self, asset: &str) -> Option<Quote> {
+        self.quotes.borrow().get(asset).map(|point| point.quote)
+    }
+
+    pub fn latest(&self) -> Arc<HashMap<String, PricePoint>> {
+        self.quotes.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> PriceStore {
+        PriceStore {
+            intake: Mutex::new(Intake::default()),
+            quotes: watch::channel(Arc::new(HashMap::new())).0,
+        }
+    }
+
+    fn tick(price: i64, timestamp: i64) -> PriceUpdate {
+        parse_tick(
+            &format!(
+                r#"{{"asset":"BTC","price":"{}","timestamp":{}}}"#,
+                price, timestamp
+            ),
+            0,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn coalesced_ticks_count_towards_confirmation_and_range() {
+        let store = store();
+        assert!(store.stage_tick(tick(100, 1)));
+        // Far outside the band: quarantined until confirmed, even while a tick is waiting
+        for timestamp in 2..4 {
+            assert!(!store.stage_tick(tick(200, timestamp)));
+        }
+        assert_eq!(
+            store.take_tick("BTC", "backpack").unwrap().update.price,
+            100
+        );
+        assert!(store.stage_tick(tick(205, 4)));
+        assert!(!store.stage_tick(tick(195, 5)));
+
+        let staged = store.take_tick("BTC", "backpack").unwrap();
+        assert_eq!(staged.update.price, 195);
+        assert_eq!((staged.low, staged.high), (195, 205));
+    }
+
+    #[test]
+    fn rejected_ticks_are_not_staged() {
+        let store = store();
+        assert!(store.stage_tick(tick(100, 2)));
+        store.take_tick("BTC", "backpack");
+        assert!(!store.stage_tick(tick(101, 1)));
+        assert!(!store.stage_tick(tick(0, 3)));
+        assert!(store.take_tick("BTC", "backpack").is_none());
+    }
+}
@@ -1,43 +1,36 @@
+use crate::modules::candles::{publish_candles, record_tick};
+use crate::modules::market_status::{publish_market_status, record_price_tick};
+use crate::modules::price_aggregator::{aggregate_price, aggregated_range};
+use crate::modules::price_store::{PricePoint, PRICE_STORE};
 use crate::modules::state::SharedEngineState;
-use serde_json::Value;
-use tokio::time::{sleep, Duration};
+use crate::modules::types::{AssetStatus, StagedTick};
 
-/// Call this once at startup to periodically print all prices.
-pub fn spawn_price_logger(state: SharedEngineState) {
-    tokio::spawn(async move {
-        loop {
-            {
-                let engine_state = state.lock().await;
-                // Print all prices in a single line
-                let mut prices: Vec<String> = Vec::new();
-                for asset in ["BTC_USDC", "ETH_USDC", "SOL_USDC", "BNB_USDC", "DOGE_USDC"] {
-                    let price = engine_state.prices.get(asset).cloned().unwrap_or(-1);
-                    prices.push(format!("{}: {}", asset, price));
-                }
-                //println!("Prices => {}", prices.join(" | "));
-            }
-            sleep(Duration::from_millis(100)).await;
-        }
-    });
-}
-
-/// Handles price updates and updates the `prices` field in `EngineState`.
-pub async fn handle_price_update(payload: &str, state: SharedEngineState) {
-    match serde_json::from_str::<Value>(payload) {
-        Ok(price_update) => {
-            if let Some(asset) = price_update["asset"].as_str() {
-                let price_opt = price_update["price"].as_i64().or_else(|| {
-                    price_update["price"]
-                        .as_str()
-                        .and_then(|raw| raw.parse::<i64>().ok())
-                });
-
-                if let Some(price) = price_opt {
-                    let mut engine_state = state.lock().await;
-                    engine_state.prices.insert(asset.to_string(), price);
-                }
-            }
+/// Apply a tick the price store has already filtered and update the `prices` field in
+/// `EngineState`. The tick is combined with the other sources' quotes and only the aggregated
+/// price reaches `prices`. Candles also take the range the aggregated price spanned over any
+/// ticks coalesced into this one.
+pub async fn handle_price_update(tick: StagedTick, state: SharedEngineState, now: i64) {
+    let update = &tick.update;
+    let asset = update.asset.as_str();
+    let mut engine_state = state.lock().await;
+    if engine_state.asset_status(asset) == Some(AssetStatus::Delisted) {
+        return;
+    }
+    if let Some(quote) = aggregate_price(&mut engine_state, update, now) {
+        engine_state.prices.insert(asset.to_string(), quote.mid);
+        engine_state.quotes.insert(asset.to_string(), quote);
+        PRICE_STORE.publish_quote(
+            asset,
+            PricePoint {
+                quote,
+                updated_at: now,
+            },
+        );
+        let range = aggregated_range(&engine_state, &tick, now);
+        let closed_candles = record_tick(&mut engine_state, asset, quote.mid, range, now);
+        publish_candles(&closed_candles).await;
+        if let Some(event) = record_price_tick(&mut engine_state, asset, update.timestamp, now) {
+            publish_market_status(&event).await;
         }
-        Err(_) => {}
     }
 }
@@ -1,62 +1,117 @@
 use crate::kafka::producer;
+use crate::modules::account_loads::{queue_until_loaded, take_ready_orders};
+use crate::modules::candles::{publish_candles, record_fill};
+use crate::modules::dedup::Intake;
 use crate::modules::execution::{apply_execution, publish_trade_outcome_for_market_order};
+use crate::modules::halts::{halt_rejection, HaltAction};
 use crate::modules::netting::apply_netting;
 use crate::modules::order_matching::{add_limit_order, match_market_order};
+use crate::modules::settlement::{remainder_outcome, resting_order_outcome};
 use crate::modules::state::OrderBook;
-use crate::modules::state::SharedEngineState;
+use crate::modules::state::{EngineState, SharedEngineState};
+use crate::modules::terms::position_term;
 use crate::modules::types::{
-    order_to_trade, CreateTradeRequest, Order, OrderStatus, OrderType, Side,
+    order_to_trade, AssetStatus, CreateTradeRequest, Order, OrderStatus, OrderType, Side,
 };
 use std::collections::VecDeque;
-use uuid::Uuid;
 
 pub async fn process_trade_create(
     state: SharedEngineState,
     req: CreateTradeRequest,
     tx: tokio::sync::mpsc::Sender<String>,
+    now: i64,
 ) {
     println!(
         "Processing trade request - correlationId: {:?}",
         req.correlation_id
     );
-    let mut engine_state = state.lock().await;
-
-    // Validate user balance - NO deduction yet
-    let current_balance = match engine_state.balances.get(&req.user_id) {
-        Some(balance) => *balance,
-        None => {
-            println!(
-                "No balance found for user: {}. Requesting balance...",
-                req.user_id
-            );
-            if let Err(e) = producer::send_balance_request(&req.user_id).await {
-                eprintln!("Failed to send balance request: {:?}", e);
+    // A redelivered or retried request gets the first delivery's answer instead of a second order
+    if let Some(ref corr_id) = req.correlation_id {
+        let mut engine_state = state.lock().await;
+        match engine_state
+            .request_dedup
+            .intake(&req.user_id, corr_id, now)
+        {
+            Intake::New => {}
+            Intake::InProgress => {
+                println!("Ignoring duplicate trade request {}: still queued", corr_id);
+                return;
+            }
+            Intake::Answered((key, response)) => {
+                println!(
+                    "Duplicate trade request {}: resending its response",
+                    corr_id
+                );
+                producer::send_trade_create_response(key, response).await;
+                return;
             }
-            engine_state
-                .pending_trades
-                .entry(req.user_id.clone())
-                .or_default()
-                .push(req);
-            return;
         }
-    };
+    }
+    execute_trade_create(state, req, tx, now).await;
+}
 
-    // Ensure holdings data is loaded before proceeding
-    if !engine_state
-        .holdings
-        .contains_key(&(req.user_id.clone(), req.asset.clone()))
+async fn execute_trade_create(
+    state: SharedEngineState,
+    req: CreateTradeRequest,
+    tx: tokio::sync::mpsc::Sender<String>,
+    now: i64,
+) {
+    let mut engine_state = state.lock().await;
+
+    // Only registered assets that are trading accept new orders
+    let asset_rejection = match engine_state.asset_status(&req.asset) {
+        None => Some(format!("Unknown asset: {}", req.asset)),
+        Some(AssetStatus::Trading) => None,
+        Some(status) => Some(format!("Asset {} is {}", req.asset, status.as_str())),
+    };
+    if let Some(reason) = asset_rejection {
+        println!("Rejected trade request: {}", reason);
+        reject_trade_request(&mut engine_state, &req, &reason).await;
+        return;
+    }
+    if let Some(reason) = halt_rejection(&engine_state, &req.user_id, &req.asset, HaltAction::Open)
     {
-        if let Err(e) = producer::send_holdings_request(&req.user_id, &req.asset).await {
-            eprintln!("Failed to send holdings request: {:?}", e);
-        }
-        engine_state
-            .pending_trades
-            .entry(req.user_id.clone())
-            .or_default()
-            .push(req);
+        println!("Rejected trade request: {}", reason);
+        reject_trade_request(&mut engine_state, &req, &reason).await;
+        return;
+    }
+    if req.leverage < 1 || req.leverage > engine_state.max_leverage(&req.asset) {
+        println!(
+            "Rejected trade request: leverage {} outside limits for {}",
+            req.leverage, req.asset
+        );
+        reject_trade_request(
+            &mut engine_state,
+            &req,
+            "Leverage outside instrument limits",
+        )
+        .await;
+        return;
+    }
+
+    // Market orders need a live price feed for the asset
+    let is_market_order = !matches!(req.order_type, Some(OrderType::Limit));
+    if is_market_order && engine_state.is_market_halted(&req.asset) {
+        println!("Rejected market order for halted asset: {}", req.asset);
+        reject_trade_request(
+            &mut engine_state,
+            &req,
+            &format!("Trading halted for {}: stale price", req.asset),
+        )
+        .await;
         return;
     }
 
+    // Balance and holdings must be loaded before the order can be validated
+    let Some(req) = queue_until_loaded(&mut engine_state, req, now).await else {
+        return;
+    };
+    let current_balance = engine_state
+        .balances
+        .get(&req.user_id)
+        .copied()
+        .unwrap_or(0);
+
     let req_qty = req.quantity.unwrap_or(0);
 
     // Determine overlap with existing opposite positions (closing) and net new exposure (opening)
@@ -104,16 +159,7 @@ pub async fn process_trade_create(
     // Validate balance
     if current_balance < required_funds {
         println!("Insufficient balance for user: {}", req.user_id);
-        let mut response_json = serde_json::json!({
-            "userId": req.user_id,
-            "status": "rejected",
-            "reason": "Insufficient balance"
-        });
-        if let Some(ref corr_id) = req.correlation_id {
-            response_json["correlationId"] = serde_json::json!(corr_id);
-        }
-        let response = response_json.to_string();
-        producer::send_trade_create_response(&req.user_id, &response).await;
+        reject_trade_request(&mut engine_state, &req, "Insufficient balance").await;
         return;
     }
 
@@ -123,7 +169,7 @@ pub async fn process_trade_create(
     }
 
     // Create the order and assign orderId only after all checks pass
-    let order_id = Uuid::new_v4().to_string();
+    let order_id = engine_state.next_order_id(now);
     let mut order = Order {
         id: order_id.clone(),
         user_id: req.user_id.clone(),
@@ -140,6 +186,8 @@ pub async fn process_trade_create(
         take_profit_percent: req.take_profit_percent,
         created_at: req.timestamp,
         expiry: req.expiry_timestamp,
+        // Fills of this order happen now; a remainder left resting restarts it when it fills
+        term: position_term(req.trade_term, req.term_rollover.unwrap_or(false), now),
     };
     // After adding to order book, publish accepted response
     let mut response_json = serde_json::json!({
@@ -160,6 +208,11 @@ pub async fn process_trade_create(
         response_json["correlationId"] = serde_json::json!(corr_id);
     }
     let response = response_json.to_string();
+    if let Some(ref corr_id) = req.correlation_id {
+        engine_state
+            .request_dedup
+            .record_response(&req.user_id, corr_id, &order_id, &response);
+    }
     producer::send_trade_create_response(&order_id, &response).await;
     let asset_key = order.asset.clone();
     // Temporarily take ownership of the asset book to avoid overlapping borrows.
@@ -169,6 +222,7 @@ pub async fn process_trade_create(
         .unwrap_or_else(OrderBook::new);
     let _prices_snapshot = engine_state.prices.clone();
 
+    let mut filled_orders = Vec::new(); // resting orders this one filled against
     match order.side {
         Side::Buy => match order.order_type {
             OrderType::Market => {
@@ -233,8 +287,8 @@ pub async fn process_trade_create(
                         };
 
                         // Update balance with PnL and returned margin
+                        engine_state.realize_pnl(&order.user_id, pnl);
                         if let Some(balance) = engine_state.balances.get_mut(&order.user_id) {
-                            *balance += pnl;
                             *balance += returned_margin;
                         }
 
@@ -334,8 +388,12 @@ pub async fn process_trade_create(
 
                     // Also apply executions for each matched counterparty (they sold)
                     for ct in matched_trades {
+                        filled_orders.push(ct.id.clone());
                         let exec_price = ct.price.unwrap_or(close_price);
                         let exec_qty = ct.quantity;
+                        let closed_candles =
+                            record_fill(&mut engine_state, &ct.asset, exec_price, exec_qty, now);
+                        publish_candles(&closed_candles).await;
                         apply_execution(
                             &mut engine_state,
                             &ct.user_id,
@@ -352,7 +410,8 @@ pub async fn process_trade_create(
                                 None
                             },
                             ct.margin,
-                            ct.created_at,
+                            now,
+                            ct.term,
                             &tx,
                         )
                         .await;
@@ -367,8 +426,17 @@ pub async fn process_trade_create(
                         add_limit_order(&mut order, &mut order_book.sell, &tx, &engine_state).await;
                     if filled > 0 {
                         for ct in matched_trades {
+                            filled_orders.push(ct.id.clone());
                             let exec_price = ct.price.unwrap_or(close_price);
                             let exec_qty = ct.quantity;
+                            let closed_candles = record_fill(
+                                &mut engine_state,
+                                &ct.asset,
+                                exec_price,
+                                exec_qty,
+                                now,
+                            );
+                            publish_candles(&closed_candles).await;
                             apply_execution(
                                 &mut engine_state,
                                 &ct.user_id,
@@ -385,7 +453,8 @@ pub async fn process_trade_create(
                                     None
                                 },
                                 ct.margin,
-                                ct.created_at,
+                                now,
+                                ct.term,
                                 &tx,
                             )
                             .await;
@@ -417,7 +486,8 @@ pub async fn process_trade_create(
                                     None
                                 },
                                 executed_margin,
-                                order.created_at,
+                                now,
+                                order.term,
                                 &tx,
                             )
                             .await;
@@ -432,8 +502,17 @@ pub async fn process_trade_create(
                             .buy
                             .entry(order.price.unwrap())
                             .or_insert(VecDeque::new());
+                        // A partial fill's remainder is recorded with the orders it filled
+                        // against, once the book is back in place
+                        let resting = (order.filled == 0)
+                            .then(|| resting_order_outcome(&engine_state, &remaining_order));
                         price_level.push_back(remaining_order);
                         println!("Added Buy limit order to book: {:?}", order.id);
+                        if let Some(outcome) = resting {
+                            if let Ok(json_string) = serde_json::to_string(&outcome) {
+                                let _ = tx.send(json_string).await;
+                            }
+                        }
                     }
                 }
             }
@@ -489,8 +568,8 @@ pub async fn process_trade_create(
                         };
 
                         // Update balance with PnL and returned margin
+                        engine_state.realize_pnl(&order.user_id, pnl);
                         if let Some(balance) = engine_state.balances.get_mut(&order.user_id) {
-                            *balance += pnl;
                             *balance += returned_margin;
                         }
 
@@ -597,8 +676,12 @@ pub async fn process_trade_create(
 
                     // Also apply executions for each matched counterparty (they bought)
                     for ct in matched_trades {
+                        filled_orders.push(ct.id.clone());
                         let exec_price = ct.price.unwrap_or(close_price);
                         let exec_qty = ct.quantity;
+                        let closed_candles =
+                            record_fill(&mut engine_state, &ct.asset, exec_price, exec_qty, now);
+                        publish_candles(&closed_candles).await;
                         apply_execution(
                             &mut engine_state,
                             &ct.user_id,
@@ -615,7 +698,8 @@ pub async fn process_trade_create(
                                 None
                             },
                             ct.margin,
-                            ct.created_at,
+                            now,
+                            ct.term,
                             &tx,
                         )
                         .await;
@@ -630,8 +714,17 @@ pub async fn process_trade_create(
                         add_limit_order(&mut order, &mut order_book.buy, &tx, &engine_state).await;
                     if filled > 0 {
                         for ct in matched_trades {
+                            filled_orders.push(ct.id.clone());
                             let exec_price = ct.price.unwrap_or(close_price);
                             let exec_qty = ct.quantity;
+                            let closed_candles = record_fill(
+                                &mut engine_state,
+                                &ct.asset,
+                                exec_price,
+                                exec_qty,
+                                now,
+                            );
+                            publish_candles(&closed_candles).await;
                             apply_execution(
                                 &mut engine_state,
                                 &ct.user_id,
@@ -648,7 +741,8 @@ pub async fn process_trade_create(
                                     None
                                 },
                                 ct.margin,
-                                ct.created_at,
+                                now,
+                                ct.term,
                                 &tx,
                             )
                             .await;
@@ -680,7 +774,8 @@ pub async fn process_trade_create(
                                     None
                                 },
                                 executed_margin,
-                                order.created_at,
+                                now,
+                                order.term,
                                 &tx,
                             )
                             .await;
@@ -695,8 +790,17 @@ pub async fn process_trade_create(
                             .sell
                             .entry(order.price.unwrap())
                             .or_insert(VecDeque::new());
+                        // A partial fill's remainder is recorded with the orders it filled
+                        // against, once the book is back in place
+                        let resting = (order.filled == 0)
+                            .then(|| resting_order_outcome(&engine_state, &remaining_order));
                         price_level.push_back(remaining_order);
                         println!("Added Sell limit order to book: {:?}", order.id);
+                        if let Some(outcome) = resting {
+                            if let Ok(json_string) = serde_json::to_string(&outcome) {
+                                let _ = tx.send(json_string).await;
+                            }
+                        }
                     }
                 }
             }
@@ -705,6 +809,19 @@ pub async fn process_trade_create(
 
     engine_state.order_books.insert(asset_key, order_book);
 
+    // Record what rests of each order a fill touched, so the DB keeps its reserved margin
+    if matches!(order.order_type, OrderType::Limit) && order.status == OrderStatus::PartiallyFilled
+    {
+        filled_orders.push(order.id.clone());
+    }
+    for order_id in filled_orders {
+        if let Some(outcome) = remainder_outcome(&engine_state, &order.asset, &order_id, now) {
+            if let Ok(json_string) = serde_json::to_string(&outcome) {
+                let _ = tx.send(json_string).await;
+            }
+        }
+    }
+
     // Do not insert trades for unfilled orders; trades are recorded upon execution via apply_execution or fill branches.
 
     println!(
@@ -712,3 +829,104 @@ pub async fn process_trade_create(
         order.user_id, order.id, order.status
     );
 }
+
+/// Record a balance fetched from the DB and run the user's orders once their account is loaded.
+pub async fn process_balance_response(
+    state: SharedEngineState,
+    user_id: String,
+    balance: i64,
+    tx: tokio::sync::mpsc::Sender<String>,
+    now: i64,
+) {
+    let mut engine_state = state.lock().await;
+    let requested = engine_state
+        .account_loads
+        .get_mut(&user_id)
+        .is_some_and(|load| std::mem::take(&mut load.balance_requested));
+    // A cached balance has moved on since the DB's copy; only a requested answer replaces it
+    if !requested && engine_state.balances.contains_key(&user_id) {
+        println!("Ignoring unrequested balance response for user {}", user_id);
+        return;
+    }
+    engine_state.load_balance(&user_id, balance);
+    println!(
+        "Engine state updated balance for user {}: {}",
+        user_id, balance
+    );
+
+    let ready = take_ready_orders(&mut engine_state, &user_id);
+    // Drop the lock before calling process_trade_create to avoid deadlock
+    drop(engine_state);
+    for trade_req in ready {
+        execute_trade_create(state.clone(), trade_req, tx.clone(), now).await;
+    }
+}
+
+/// Record holdings fetched from the DB and run the user's orders once their account is loaded.
+pub async fn process_holdings_response(
+    state: SharedEngineState,
+    user_id: String,
+    asset: String,
+    held_quantity: i64,
+    tx: tokio::sync::mpsc::Sender<String>,
+    now: i64,
+) {
+    let mut engine_state = state.lock().await;
+    let requested = engine_state
+        .account_loads
+        .get_mut(&user_id)
+        .is_some_and(|load| load.holdings_requested.remove(&asset));
+    let key = (user_id.clone(), asset.clone());
+    if !requested && engine_state.holdings.contains_key(&key) {
+        println!(
+            "Ignoring unrequested holdings response for user {} asset {}",
+            user_id, asset
+        );
+        return;
+    }
+    engine_state.holdings.insert(key, held_quantity);
+    println!(
+        "Engine state updated holdings for user {} asset {}: {}",
+        user_id, asset, held_quantity
+    );
+
+    let ready = take_ready_orders(&mut engine_state, &user_id);
+    // Drop the lock before calling process_trade_create to avoid deadlock
+    drop(engine_state);
+    for trade_req in ready {
+        execute_trade_create(state.clone(), trade_req, tx.clone(), now).await;
+    }
+}
+
+/// Publish a rejected trade-create-response for a request that will not be executed.
+async fn reject_trade_request(
+    engine_state: &mut EngineState,
+    req: &CreateTradeRequest,
+    reason: &str,
+) {
+    fail_trade_request(engine_state, req, "rejected", reason).await;
+}
+
+/// Publish a trade-create-response with a failure `status` for a request that will not be executed.
+pub async fn fail_trade_request(
+    engine_state: &mut EngineState,
+    req: &CreateTradeRequest,
+    status: &str,
+    reason: &str,
+) {
+    let mut response_json = serde_json::json!({
+        "userId": req.user_id,
+        "status": status,
+        "reason": reason
+    });
+    if let Some(ref corr_id) = req.correlation_id {
+        response_json["correlationId"] = serde_json::json!(corr_id);
+    }
+    let response = response_json.to_string();
+    if let Some(ref corr_id) = req.correlation_id {
+        engine_state
+            .request_dedup
+            .record_response(&req.user_id, corr_id, &req.user_id, &response);
+    }
+    producer::send_trade_create_response(&req.user_id, &response).await;
+}
@@ -0,0 +1,241 @@
+use crate::kafka::producer;
+use crate::modules::config::CONFIG;
+use crate::modules::state::{EngineState, SharedEngineState};
+use crate::modules::types::{
+    ReconciliationKey, ReconciliationKind, ReconciliationMismatch, ReconciliationReport,
+    ReconciliationRun,
+};
+use std::collections::BTreeSet;
+
+/// Prefix of the request ids reconciliation puts on balance and holdings requests, so their
+/// answers are told apart from the ones that load accounts for queued orders.
+pub const RECONCILIATION_PREFIX: &str = "reconcile-";
+
+/// Start a reconciliation run for every active user: anyone with an open position or a resting
+/// order. A run still awaiting answers is closed first and its report returned alongside.
+pub fn start_reconciliation(
+    engine_state: &mut EngineState,
+    now: i64,
+) -> (Option<ReconciliationReport>, Option<ReconciliationRun>) {
+    let unfinished = engine_state
+        .reconciliation
+        .take()
+        .map(|run| finish_run(engine_state, run, now));
+
+    let users: BTreeSet<String> = engine_state
+        .open_trades
+        .values()
+        .map(|trade| trade.user_id.clone())
+        .chain(
+            engine_state
+                .order_books
+                .values()
+                .flat_map(|book| book.buy.values().chain(book.sell.values()))
+                .flatten()
+                .map(|order| order.user_id.clone()),
+        )
+        .collect();
+    let mut awaiting: BTreeSet<ReconciliationKey> = users
+        .iter()
+        .map(|user_id| ReconciliationKey {
+            kind: ReconciliationKind::Balance,
+            user_id: user_id.clone(),
+            asset: None,
+        })
+        .collect();
+    awaiting.extend(
+        engine_state
+            .holdings
+            .keys()
+            .filter(|(user_id, _)| users.contains(user_id))
+            .map(|(user_id, asset)| ReconciliationKey {
+                kind: ReconciliationKind::Holdings,
+                user_id: user_id.clone(),
+                asset: Some(asset.clone()),
+            }),
+    );
+    if awaiting.is_empty() {
+        return (unfinished, None);
+    }
+
+    let run = ReconciliationRun {
+        run_id: format!("{}{}", RECONCILIATION_PREFIX, now),
+        started_at: now,
+        awaiting,
+        checked: 0,
+        mismatches: Vec::new(),
+    };
+    engine_state.reconciliation = Some(run.clone());
+    (unfinished, Some(run))
+}
+
+/// Compare one database answer with the engine's value. Returns the report once the run has
+/// every answer it was waiting for.
+pub fn apply_reconciliation_answer(
+    engine_state: &mut EngineState,
+    run_id: &str,
+    key: ReconciliationKey,
+    database: i64,
+    now: i64,
+) -> Option<ReconciliationReport> {
+    let run = engine_state.reconciliation.as_mut()?;
+    if run.run_id != run_id || !run.awaiting.remove(&key) {
+        return None;
+    }
+    run.checked += 1;
+
+    let engine = match key.kind {
+        ReconciliationKind::Balance => engine_state.balances.get(&key.user_id).copied(),
+        ReconciliationKind::Holdings => engine_state
+            .holdings
+            .get(&(key.user_id.clone(), key.asset.clone().unwrap_or_default()))
+            .copied(),
+    }
+    .unwrap_or(0);
+
+    if engine != database {
+        let corrected = should_correct(engine_state, &key, engine, database);
+        if corrected {
+            match key.kind {
+                ReconciliationKind::Balance => engine_state.load_balance(&key.user_id, database),
+                ReconciliationKind::Holdings => {
+                    engine_state.holdings.insert(
+                        (key.user_id.clone(), key.asset.clone().unwrap_or_default()),
+                        database,
+                    );
+                }
+            }
+            println!(
+                "Reconciliation corrected {:?} for user {} {}: {} -> {}",
+                key.kind,
+                key.user_id,
+                key.asset.as_deref().unwrap_or(""),
+                engine,
+                database
+            );
+        }
+        if let Some(run) = engine_state.reconciliation.as_mut() {
+            run.mismatches.push(ReconciliationMismatch {
+                key,
+                engine,
+                database,
+                difference: database - engine,
+                corrected,
+            });
+        }
+    }
+
+    if engine_state
+        .reconciliation
+        .as_ref()
+        .is_some_and(|run| run.awaiting.is_empty())
+    {
+        let run = engine_state.reconciliation.take()?;
+        return Some(finish_run(engine_state, run, now));
+    }
+    None
+}
+
+/// The engine only adopts the database value for kinds configured for auto-correction, for
+/// differences within the configured limit, and only once the previous run saw the very same
+/// mismatch: a single sighting may just be an outcome the database hasn't applied yet.
+fn should_correct(
+    engine_state: &EngineState,
+    key: &ReconciliationKey,
+    engine: i64,
+    database: i64,
+) -> bool {
+    let kind = match key.kind {
+        ReconciliationKind::Balance => "balance",
+        ReconciliationKind::Holdings => "holdings",
+    };
+    CONFIG
+        .reconciliation_auto_correct
+        .iter()
+        .any(|allowed| allowed == kind)
+        && (database - engine).abs() <= CONFIG.reconciliation_max_correction
+        && engine_state
+            .reconciliation_mismatches
+            .iter()
+            .any(|seen| &seen.key == key && seen.engine == engine && seen.database == database)
+}
+
+fn finish_run(
+    engine_state: &mut EngineState,
+    run: ReconciliationRun,
+    now: i64,
+) -> ReconciliationReport {
+    engine_state.reconciliation_mismatches = run
+        .mismatches
+        .iter()
+        .filter(|mismatch| !mismatch.corrected)
+        .cloned()
+        .collect();
+    ReconciliationReport {
+        run_id: run.run_id,
+        started_at: run.started_at,
+        completed_at: now,
+        checked: run.checked,
+        mismatches: run.mismatches,
+        missing: run.awaiting.into_iter().collect(),
+    }
+}
+
+/// Run the reconciliation job: close any unfinished run, then ask the database for the
+/// balances and holdings of every active user.
+pub async fn process_reconciliation(state: SharedEngineState, now: i64) -> bool {
+    let (unfinished, run) = start_reconciliation(&mut *state.lock().await, now);
+    let changed = unfinished.is_some() || run.is_some();
+    if let Some(report) = unfinished {
+        publish_reconciliation_report(&report).await;
+    }
+    if let Some(run) = run {
+        println!(
+            "Reconciliation {} started for {} values",
+            run.run_id,
+            run.awaiting.len()
+        );
+        for key in &run.awaiting {
+            match (&key.kind, &key.asset) {
+                (ReconciliationKind::Holdings, Some(asset)) => {
+                    producer::send_holdings_check(&key.user_id, asset, &run.run_id).await
+                }
+                _ => producer::send_balance_check(&key.user_id, &run.run_id).await,
+            }
+        }
+    }
+    changed
+}
+
+/// Apply a database answer to the running reconciliation and publish the report if it was the
+/// last one awaited.
+pub async fn process_reconciliation_answer(
+    state: SharedEngineState,
+    run_id: String,
+    key: ReconciliationKey,
+    database: i64,
+    now: i64,
+) {
+    let report = apply_reconciliation_answer(&mut *state.lock().await, &run_id, key, database, now);
+    if let Some(report) = report {
+        publish_reconciliation_report(&report).await;
+    }
+}
+
+pub async fn publish_reconciliation_report(report: &ReconciliationReport) {
+    println!(
+        "Reconciliation {} finished: {} checked, {} mismatched ({} corrected), {} unanswered",
+        report.run_id,
+        report.checked,
+        report.mismatches.len(),
+        report
+            .mismatches
+            .iter()
+            .filter(|mismatch| mismatch.corrected)
+            .count(),
+        report.missing.len()
+    );
+    if let Ok(json_string) = serde_json::to_string(report) {
+        producer::publish_reconciliation_report(&report.run_id, &json_string).await;
+    }
+}
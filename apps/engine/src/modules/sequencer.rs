@@ -0,0 +1,173 @@
+use crate::modules::assets::report_asset_status;
+use crate::modules::config::CONFIG;
+use crate::modules::invariants::check_invariants;
+use crate::modules::journal::{process_batch, JournalEvent, TimerJob};
+use crate::modules::price_store::{parse_tick, PRICE_STORE};
+use crate::modules::snapshot::snapshot_state;
+use crate::modules::state::{EngineState, SharedEngineState};
+use crate::modules::types::{AggregatedPrice, Candle, CandleInterval};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
+
+/// What the sequencer is asked to apply.
+pub enum SequencerInput {
+    Event(JournalEvent),
+    // A tick is waiting in the price store; ticks staged after it replace it there, so a
+    // backlog of prices never builds up ahead of orders
+    PriceTick { asset: String, source: String },
+    Task(SequencerTask),
+}
+
+/// Housekeeping that reads state without changing it. The sequencer runs it between events,
+/// so it sees state as of an event boundary and never contends with events for the locks.
+#[derive(Debug, Clone, Copy)]
+pub enum SequencerTask {
+    Snapshot,
+    InvariantCheck,
+    AssetReport,
+}
+
+/// Inbound channel of the sequencer: every state-mutating event goes through it.
+pub type EventSender = mpsc::Sender<SequencerInput>;
+
+/// Latest market data published by the sequencer, for queries that mustn't wait on it.
+pub type MarketViewReceiver = watch::Receiver<Arc<MarketView>>;
+
+/// Read-only copy of the state price and candle queries are answered from. Histories are
+/// shared with the state per series: a capture copies pointers, and the state copies a series
+/// only when it next writes to it. Candles in progress are few and copied outright.
+#[derive(Default)]
+pub struct MarketView {
+    pub price_history: HashMap<String, Arc<VecDeque<AggregatedPrice>>>,
+    pub open_candles: HashMap<String, HashMap<CandleInterval, Candle>>,
+    pub closed_candles: HashMap<String, HashMap<CandleInterval, Arc<VecDeque<Candle>>>>,
+}
+
+impl MarketView {
+    fn capture(engine_state: &EngineState) -> Self {
+        Self {
+            price_history: engine_state.price_history.clone(),
+            open_candles: engine_state.open_candles.clone(),
+            closed_candles: engine_state.closed_candles.clone(),
+        }
+    }
+}
+
+/// Whether applying `event` can change what `MarketView` holds: prices, or candles through
+/// fills and closes.
+fn moves_market(event: &JournalEvent) -> bool {
+    matches!(
+        event,
+        JournalEvent::PriceUpdate(_)
+            | JournalEvent::TradeCreate(_)
+            | JournalEvent::BalanceResponse { .. }
+            | JournalEvent::HoldingsResponse { .. }
+            | JournalEvent::Admin(_)
+            | JournalEvent::Timer(TimerJob::CandleClose)
+    )
+}
+
+/// Call this once at startup, after the journal has been replayed. Starts the single writer
+/// that applies events and runs tasks strictly in the order they arrive on the returned sender.
+/// Events queued when it gets to run are journaled as one batch, and a fresh `MarketView` is
+/// published after each batch that moves the market.
+pub async fn spawn_sequencer(
+    state: SharedEngineState,
+    outcomes: mpsc::Sender<String>,
+) -> (EventSender, MarketViewReceiver) {
+    let (events, mut inbound) = mpsc::channel::<SequencerInput>(CONFIG.sequencer_queue_len.max(1));
+    let initial = {
+        let engine_state = state.lock().await;
+        PRICE_STORE.seed(&engine_state);
+        MarketView::capture(&engine_state)
+    };
+    let (view_tx, view_rx) = watch::channel(Arc::new(initial));
+
+    tokio::spawn(async move {
+        while let Some(input) = inbound.recv().await {
+            let mut inputs = vec![input];
+            while inputs.len() < CONFIG.journal_batch_max.max(1) {
+                match inbound.try_recv() {
+                    Ok(input) => inputs.push(input),
+                    Err(_) => break,
+                }
+            }
+            let mut batch = Vec::new();
+            for input in inputs {
+                match input {
+                    SequencerInput::Event(event) => batch.push(event),
+                    SequencerInput::PriceTick { asset, source } => batch.extend(
+                        PRICE_STORE
+                            .take_tick(&asset, &source)
+                            .map(JournalEvent::PriceUpdate),
+                    ),
+                    SequencerInput::Task(task) => {
+                        apply_batch(&state, std::mem::take(&mut batch), &outcomes, &view_tx).await;
+                        run_task(&state, task).await;
+                    }
+                }
+            }
+            apply_batch(&state, batch, &outcomes, &view_tx).await;
+        }
+    });
+
+    (events, view_rx)
+}
+
+async fn apply_batch(
+    state: &SharedEngineState,
+    events: Vec<JournalEvent>,
+    outcomes: &mpsc::Sender<String>,
+    view_tx: &watch::Sender<Arc<MarketView>>,
+) {
+    if events.is_empty() {
+        return;
+    }
+    let publish = events.iter().any(moves_market);
+    process_batch(state, events, outcomes).await;
+    if publish {
+        let view = MarketView::capture(&*state.lock().await);
+        view_tx.send_replace(Arc::new(view));
+    }
+}
+
+async fn run_task(state: &SharedEngineState, task: SequencerTask) {
+    match task {
+        SequencerTask::Snapshot => snapshot_state(state).await,
+        SequencerTask::InvariantCheck => check_invariants(state).await,
+        SequencerTask::AssetReport => report_asset_status(state).await,
+    }
+}
+
+/// Queue `event` for the sequencer, waiting while its queue is full.
+pub async fn submit(events: &EventSender, event: JournalEvent) {
+    if let Err(e) = events.send(SequencerInput::Event(event)).await {
+        if let SequencerInput::Event(event) = e.0 {
+            eprintln!("Sequencer stopped, dropping event {:?}", event);
+        }
+    }
+}
+
+/// Queue `task` for the sequencer, to run once the events queued ahead of it are applied.
+pub async fn submit_task(events: &EventSender, task: SequencerTask) {
+    if events.send(SequencerInput::Task(task)).await.is_err() {
+        eprintln!("Sequencer stopped, dropping task {:?}", task);
+    }
+}
+
+/// Parse and filter a raw "price-updates" payload in the price store, and let the sequencer
+/// know of an accepted tick unless one from the same source for the same asset is already
+/// waiting: this one then replaces it.
+pub async fn submit_price_tick(events: &EventSender, payload: &str) {
+    let Some(update) = parse_tick(payload, chrono::Utc::now().timestamp_millis()) else {
+        return;
+    };
+    let input = SequencerInput::PriceTick {
+        asset: update.asset.clone(),
+        source: update.source.clone(),
+    };
+    if PRICE_STORE.stage_tick(update) && events.send(input).await.is_err() {
+        eprintln!("Sequencer stopped, dropping a staged price tick");
+    }
+}
@@ -0,0 +1,212 @@
+use crate::modules::margin::position_update_outcome;
+use crate::modules::pnl::calculate_pnl;
+use crate::modules::state::EngineState;
+use crate::modules::types::{Order, OrderType, Side, TradeOutcome};
+
+/// Close an open trade at `price`: realize PnL, release its locked margin back to the
+/// user's balance and take the exposure out of the holdings ledger.
+/// With `cap_loss_at_margin` the loss is limited to the locked margin (isolated liquidation).
+/// Returns the outcome to publish, or `None` if the trade is not open.
+pub fn close_trade_at_price(
+    engine_state: &mut EngineState,
+    trade_id: &str,
+    price: i64,
+    status: &str,
+    reason: Option<&str>,
+    cap_loss_at_margin: bool,
+    timestamp: i64,
+) -> Option<TradeOutcome> {
+    let mut trade = engine_state.open_trades.remove(trade_id)?;
+    trade.close_price = Some(price);
+    let pnl = calculate_pnl(&trade);
+    let locked_margin = engine_state
+        .locked_margins
+        .remove(trade_id)
+        .unwrap_or(trade.margin);
+
+    let mut margin_return = locked_margin + pnl;
+    if cap_loss_at_margin {
+        margin_return = margin_return.max(0);
+    }
+    let realized_pnl = margin_return - locked_margin;
+
+    engine_state.realize_pnl(&trade.user_id, realized_pnl);
+    if let Some(balance) = engine_state.balances.get_mut(&trade.user_id) {
+        *balance += locked_margin;
+    }
+
+    let holdings_key = (trade.user_id.clone(), trade.asset.clone());
+    if let Some(holdings) = engine_state.holdings.get_mut(&holdings_key) {
+        match trade.side {
+            Side::Buy => *holdings -= trade.quantity,
+            Side::Sell => *holdings += trade.quantity,
+        }
+    }
+
+    // A partial fill's remainder can stay on the book after its position closes
+    let (resting_quantity, resting_margin) =
+        resting_remainder(engine_state, &trade.asset, trade_id).map_or((0, 0), |order| {
+            (order.quantity - order.filled, order.margin)
+        });
+
+    println!(
+        "Trade {} {} at price {} with PnL: {}. Reason: {}",
+        trade_id,
+        status,
+        price,
+        realized_pnl,
+        reason.unwrap_or("-")
+    );
+
+    Some(TradeOutcome {
+        trade_id: trade.id.clone(),
+        user_id: trade.user_id.clone(),
+        asset: trade.asset.clone(),
+        side: trade.side.clone(),
+        quantity: trade.quantity,
+        entry_price: trade.entry_price,
+        close_price: Some(price),
+        pnl: Some(realized_pnl),
+        status: Some(status.to_string()),
+        timestamp: Some(timestamp),
+        margin: Some(locked_margin),
+        leverage: Some(trade.leverage),
+        slippage: Some(0),
+        reason: reason.map(|r| r.to_string()),
+        success: Some(true),
+        order_type: Some(OrderType::Market),
+        limit_price: None,
+        updated_balance: engine_state.balances.get(&trade.user_id).copied(),
+        updated_holdings: engine_state.holdings.get(&holdings_key).copied(),
+        locked_margin: Some(0),
+        liquidation_price: None,
+        fee: None,
+        term: trade.term,
+        resting_quantity: Some(resting_quantity),
+        resting_margin: Some(resting_margin),
+    })
+}
+
+/// Cancel a resting order that was already taken out of its order book, refunding the
+/// margin still reserved for its unfilled quantity to the user's balance.
+pub fn cancel_resting_order(
+    engine_state: &mut EngineState,
+    order: &Order,
+    reason: &str,
+    timestamp: i64,
+) -> TradeOutcome {
+    let refund = order.margin.max(0);
+    if let Some(balance) = engine_state.balances.get_mut(&order.user_id) {
+        *balance += refund;
+    }
+    let remaining_quantity = (order.quantity - order.filled).max(0);
+
+    println!(
+        "Order {} cancelled with {} units unfilled. Refunded margin: {}. Reason: {}",
+        order.id, remaining_quantity, refund, reason
+    );
+
+    TradeOutcome {
+        trade_id: order.id.clone(),
+        user_id: order.user_id.clone(),
+        asset: order.asset.clone(),
+        side: order.side.clone(),
+        quantity: remaining_quantity,
+        entry_price: order.price,
+        close_price: None,
+        pnl: Some(0),
+        status: Some("cancelled".to_string()),
+        timestamp: Some(timestamp),
+        margin: Some(refund),
+        leverage: Some(order.leverage),
+        slippage: Some(0),
+        reason: Some(reason.to_string()),
+        success: Some(true),
+        order_type: Some(order.order_type.clone()),
+        limit_price: order.price,
+        updated_balance: engine_state.balances.get(&order.user_id).copied(),
+        updated_holdings: engine_state
+            .holdings
+            .get(&(order.user_id.clone(), order.asset.clone()))
+            .copied(),
+        locked_margin: Some(0),
+        liquidation_price: None,
+        fee: None,
+        term: order.term,
+        resting_quantity: Some(0),
+        resting_margin: Some(0),
+    }
+}
+
+/// Outcome recording a limit order that rests on the book without any fill, so the DB holds
+/// it (status OPEN) and a fresh engine can put it back on the book.
+pub fn resting_order_outcome(engine_state: &EngineState, order: &Order) -> TradeOutcome {
+    TradeOutcome {
+        trade_id: order.id.clone(),
+        user_id: order.user_id.clone(),
+        asset: order.asset.clone(),
+        side: order.side.clone(),
+        quantity: order.quantity - order.filled,
+        entry_price: order.price,
+        close_price: None,
+        pnl: None,
+        status: Some("open".to_string()),
+        timestamp: Some(order.created_at),
+        margin: Some(order.margin),
+        leverage: Some(order.leverage),
+        slippage: Some(0),
+        reason: None,
+        success: Some(true),
+        order_type: Some(order.order_type.clone()),
+        limit_price: order.price,
+        updated_balance: engine_state.balances.get(&order.user_id).copied(),
+        updated_holdings: engine_state
+            .holdings
+            .get(&(order.user_id.clone(), order.asset.clone()))
+            .copied(),
+        locked_margin: Some(order.margin),
+        liquidation_price: None,
+        fee: None,
+        term: order.term,
+        resting_quantity: Some(0),
+        resting_margin: Some(0),
+    }
+}
+
+/// The part of order `order_id` still resting on `asset`'s book, if any.
+pub fn resting_remainder<'a>(
+    engine_state: &'a EngineState,
+    asset: &str,
+    order_id: &str,
+) -> Option<&'a Order> {
+    let book = engine_state.order_books.get(asset)?;
+    book.buy
+        .values()
+        .chain(book.sell.values())
+        .flatten()
+        .find(|order| order.id == order_id)
+}
+
+/// Outcome recording what rests of order `order_id` once fills have touched it, so the DB keeps
+/// the margin reserved for a partial fill's remainder. The remainder shares its id with the
+/// position the fill opened and is recorded on that row; one whose fills only closed other
+/// positions is recorded as a resting order in its own right.
+pub fn remainder_outcome(
+    engine_state: &EngineState,
+    asset: &str,
+    order_id: &str,
+    timestamp: i64,
+) -> Option<TradeOutcome> {
+    let remainder = resting_remainder(engine_state, asset, order_id);
+    match engine_state.open_trades.get(order_id) {
+        Some(trade) => {
+            let mut outcome =
+                position_update_outcome(engine_state, trade, "partially_filled", timestamp);
+            outcome.resting_quantity =
+                Some(remainder.map_or(0, |order| order.quantity - order.filled));
+            outcome.resting_margin = Some(remainder.map_or(0, |order| order.margin));
+            Some(outcome)
+        }
+        None => remainder.map(|order| resting_order_outcome(engine_state, order)),
+    }
+}
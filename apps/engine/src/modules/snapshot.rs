@@ -0,0 +1,102 @@
+use crate::modules::config::CONFIG;
+use crate::modules::journal::JOURNAL;
+use crate::modules::sequencer::{submit_task, EventSender, SequencerTask};
+use crate::modules::state::{EngineState, SharedEngineState};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use tokio::time::{sleep, Duration};
+
+/// Bumped whenever a change to `EngineState` can't be read by older snapshot loaders.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    taken_at: i64,
+    state: &'a EngineState,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    version: u32,
+    taken_at: i64,
+    state: EngineState,
+}
+
+/// Write `engine_state` to `path` atomically: the snapshot goes to a temporary file that is
+/// synced and then renamed over the previous snapshot.
+pub fn write_snapshot(engine_state: &EngineState, path: &str, now: i64) -> io::Result<()> {
+    let snapshot = SnapshotRef {
+        version: SNAPSHOT_VERSION,
+        taken_at: now,
+        state: engine_state,
+    };
+    let json = serde_json::to_vec(&snapshot)?;
+    let tmp_path = format!("{}.tmp", path);
+    {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&json)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// Load the snapshot at `path`. Returns `Ok(None)` when there is no snapshot yet.
+pub fn load_snapshot(path: &str) -> io::Result<Option<EngineState>> {
+    if !Path::new(path).exists() {
+        return Ok(None);
+    }
+    let bytes = fs::read(path)?;
+    let snapshot: Snapshot = serde_json::from_slice(&bytes)?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "snapshot version {} not supported (expected {})",
+                snapshot.version, SNAPSHOT_VERSION
+            ),
+        ));
+    }
+    println!(
+        "Restored engine state from {} taken at {}: {} balances, {} open trades, {} order books",
+        path,
+        snapshot.taken_at,
+        snapshot.state.balances.len(),
+        snapshot.state.open_trades.len(),
+        snapshot.state.order_books.len()
+    );
+    Ok(Some(snapshot.state))
+}
+
+/// Snapshot shared state to the configured path, then drop the journal entries it covers.
+pub async fn snapshot_state(state: &SharedEngineState) {
+    // Holding the journal keeps any event from being applied while the snapshot is taken
+    let mut journal = JOURNAL.lock().await;
+    let engine_state = state.lock().await;
+    let now = chrono::Utc::now().timestamp_millis();
+    if let Err(e) = write_snapshot(&engine_state, &CONFIG.snapshot_path, now) {
+        eprintln!(
+            "Failed to write snapshot to {}: {}",
+            CONFIG.snapshot_path, e
+        );
+        return;
+    }
+    if let Err(e) = journal.truncate() {
+        eprintln!("Failed to truncate journal {}: {}", CONFIG.journal_path, e);
+    }
+}
+
+/// Call this once at startup to have the sequencer snapshot state at the configured interval.
+pub fn spawn_snapshot_writer(events: EventSender) {
+    tokio::spawn(async move {
+        loop {
+            sleep(Duration::from_millis(
+                CONFIG.snapshot_interval_ms.max(1) as u64
+            ))
+            .await;
+            submit_task(&events, SequencerTask::Snapshot).await;
+        }
+    });
+}
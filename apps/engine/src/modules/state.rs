@@ -1,7 +1,17 @@
-use crate::modules::types::{CreateTradeRequest, Order, Trade};
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use crate::modules::config::CONFIG;
+use crate::modules::dedup::RequestDedup;
+use crate::modules::price_store::PRICE_STORE;
+use crate::modules::types::{
+    AccountLoad, AggregatedPrice, AssetInfo, AssetStatus, Candle, CandleInterval, HaltScope,
+    HaltState, Ledger, MarginMode, Order, Quote, ReconciliationMismatch, ReconciliationRun, Side,
+    SourceQuote, Trade,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use uuid::Uuid;
+#[derive(Serialize, Deserialize)]
 pub struct OrderBook {
     pub buy: BTreeMap<i64, VecDeque<Order>>,
     pub sell: BTreeMap<i64, VecDeque<Order>>,
@@ -16,30 +26,132 @@ impl OrderBook {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(default)] // fields added after a snapshot was written start empty
 pub struct EngineState {
-    pub balances: HashMap<String, i64>, // user_id -> balance (scaled integer)
-    pub open_trades: HashMap<String, Trade>, // order_id -> Trade
+    pub assets: HashMap<String, AssetInfo>, // asset -> registry entry; orders for other assets are rejected
+    pub balances: HashMap<String, i64>,     // user_id -> balance (scaled integer)
+    pub open_trades: BTreeMap<String, Trade>, // order_id -> Trade, ordered so replays visit positions identically
     pub order_books: HashMap<String, OrderBook>, // asset -> order book
-    pub prices: HashMap<String, i64>,   // asset -> price (scaled integer)
-    pub pending_trades: HashMap<String, Vec<CreateTradeRequest>>, // user_id -> trades
+    pub prices: HashMap<String, i64>,         // asset -> mid price (scaled integer)
+    pub quotes: HashMap<String, Quote>, // asset -> bid / ask / mid; readers use the price store's copy
+    pub price_updated_at: HashMap<String, i64>, // asset -> ms since epoch of the last accepted tick
+    pub stale_markets: HashSet<String>, // assets halted because their price feed went stale
+    pub price_sources: HashMap<String, BTreeMap<String, SourceQuote>>, // asset -> source -> last accepted quote
+    pub price_history: HashMap<String, Arc<VecDeque<AggregatedPrice>>>, // asset -> recent aggregated prices, oldest first; shared with the market view
+    pub open_candles: HashMap<String, HashMap<CandleInterval, Candle>>, // asset -> interval -> candle in progress
+    pub closed_candles: HashMap<String, HashMap<CandleInterval, Arc<VecDeque<Candle>>>>, // asset -> interval -> recent closed candles, oldest first; shared with the market view
+    pub request_dedup: RequestDedup, // correlation ids of recent trade requests and their responses
+    pub account_loads: HashMap<String, AccountLoad>, // user_id -> balance/holdings load and the orders waiting on it
+    #[serde(with = "holdings_entries")]
     pub holdings: HashMap<(String, String), i64>, // user_id , asset -> quantity
-    pub locked_margins: HashMap<String, i64>, // order_id -> locked margin
+    pub locked_margins: HashMap<String, i64>,        // order_id -> locked margin
+    pub margin_modes: HashMap<String, MarginMode>,   // user_id -> margin mode (isolated if absent)
+    pub margin_calls: HashMap<String, usize>, // order_id (isolated) or user_id (cross) -> breached margin call levels
+    pub last_financing_rollover: Option<i64>, // ms since epoch of the last rollover charged
+    pub missed_rollovers: BTreeMap<String, Vec<i64>>, // order_id -> rollovers skipped while its market was halted
+    pub global_halt: Option<HaltState>,               // admin halt on all trading
+    pub asset_halts: HashMap<String, HaltState>,      // asset -> admin halt
+    pub user_halts: HashMap<String, HaltState>,       // user_id -> admin halt
+    pub order_sequence: u64, // orders created so far; feeds deterministic order ids
+    pub ledger: Ledger,      // money in and out of user funds, for the conservation check
+    pub reconciliation: Option<ReconciliationRun>, // DB comparison awaiting answers
+    pub reconciliation_mismatches: Vec<ReconciliationMismatch>, // left uncorrected by the last run
+    pub journal_seq: u64,    // last journal entry applied to this state
 }
 
 impl EngineState {
     pub fn new() -> Self {
         Self {
+            assets: CONFIG
+                .default_assets
+                .iter()
+                .map(|asset| {
+                    let info = AssetInfo {
+                        status: AssetStatus::Trading,
+                        max_leverage: None,
+                    };
+                    (asset.clone(), info)
+                })
+                .collect(),
             balances: HashMap::new(),
-            open_trades: HashMap::new(),
+            open_trades: BTreeMap::new(),
             order_books: HashMap::new(),
             prices: HashMap::new(),
-            pending_trades: HashMap::new(),
+            quotes: HashMap::new(),
+            price_updated_at: HashMap::new(),
+            stale_markets: HashSet::new(),
+            price_sources: HashMap::new(),
+            price_history: HashMap::new(),
+            open_candles: HashMap::new(),
+            closed_candles: HashMap::new(),
+            request_dedup: RequestDedup::default(),
+            account_loads: HashMap::new(),
             holdings: HashMap::new(),
             locked_margins: HashMap::new(),
+            margin_modes: HashMap::new(),
+            margin_calls: HashMap::new(),
+            last_financing_rollover: None,
+            missed_rollovers: BTreeMap::new(),
+            global_halt: None,
+            asset_halts: HashMap::new(),
+            user_halts: HashMap::new(),
+            order_sequence: 0,
+            ledger: Ledger::default(),
+            reconciliation: None,
+            reconciliation_mismatches: Vec::new(),
+            journal_seq: 0,
         }
     }
 }
 
+impl Default for EngineState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Holdings keyed by (user_id, asset) serialize as a list of entries, since JSON object keys
+/// must be strings.
+mod holdings_entries {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct HoldingEntry {
+        user_id: String,
+        asset: String,
+        quantity: i64,
+    }
+
+    pub fn serialize<S: Serializer>(
+        holdings: &HashMap<(String, String), i64>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut entries: Vec<HoldingEntry> = holdings
+            .iter()
+            .map(|((user_id, asset), quantity)| HoldingEntry {
+                user_id: user_id.clone(),
+                asset: asset.clone(),
+                quantity: *quantity,
+            })
+            .collect();
+        entries.sort_by(|a, b| (&a.user_id, &a.asset).cmp(&(&b.user_id, &b.asset)));
+        entries.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<(String, String), i64>, D::Error> {
+        let entries = Vec::<HoldingEntry>::deserialize(deserializer)?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| ((entry.user_id, entry.asset), entry.quantity))
+            .collect())
+    }
+}
+
 // Shared state type for concurrent access
 pub type SharedEngineState = Arc<Mutex<EngineState>>;
 
@@ -59,4 +171,104 @@ impl EngineState {
     pub fn release_locked_margin(&mut self, order_id: &str) -> i64 {
         self.locked_margins.remove(order_id).unwrap_or(0)
     }
+
+    /// Id for the next order created at `now`. Derived from state alone so a journal replay
+    /// hands out the same ids as the original run.
+    pub fn next_order_id(&mut self, now: i64) -> String {
+        self.order_sequence += 1;
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&(now as u64).to_be_bytes());
+        bytes[8..].copy_from_slice(&self.order_sequence.to_be_bytes());
+        // Version 8 marks the id as built from these fields rather than random
+        Uuid::new_v8(bytes).to_string()
+    }
+
+    /// Set a user's balance as loaded from the DB. A first load is a deposit; replacing a
+    /// balance the engine already held is drift between the two and booked as an adjustment.
+    pub fn load_balance(&mut self, user_id: &str, balance: i64) {
+        match self.balances.insert(user_id.to_string(), balance) {
+            None => self.ledger.deposits += balance,
+            Some(previous) => self.ledger.adjustments += balance - previous,
+        }
+    }
+
+    /// Credit realized PnL (negative for a loss) to a user's balance.
+    pub fn realize_pnl(&mut self, user_id: &str, pnl: i64) {
+        if let Some(balance) = self.balances.get_mut(user_id) {
+            *balance += pnl;
+            self.ledger.realized_pnl += pnl;
+        }
+    }
+
+    /// Take a fee (negative for a credit) from a user's balance.
+    pub fn charge_fee(&mut self, user_id: &str, fee: i64) {
+        if let Some(balance) = self.balances.get_mut(user_id) {
+            *balance -= fee;
+            self.ledger.fees += fee;
+        }
+    }
+
+    pub fn margin_mode(&self, user_id: &str) -> MarginMode {
+        self.margin_modes.get(user_id).copied().unwrap_or_default()
+    }
+
+    /// Whether closing a position of `user_id` loses at most its margin: isolated positions
+    /// do, cross positions settle the full loss against the shared balance.
+    pub fn caps_loss_at_margin(&self, user_id: &str) -> bool {
+        self.margin_mode(user_id) == MarginMode::Isolated
+    }
+
+    /// Price a `side` execution on `asset` fills at: buys lift the ask, sells hit the bid.
+    /// Quotes come from the price store, which the sequencer keeps in step with `quotes`.
+    /// Falls back to the mid for assets without a quote.
+    pub fn execution_price(&self, asset: &str, side: &Side) -> Option<i64> {
+        match PRICE_STORE.quote(asset) {
+            Some(quote) => Some(match side {
+                Side::Buy => quote.ask,
+                Side::Sell => quote.bid,
+            }),
+            None => self.prices.get(asset).copied(),
+        }
+    }
+
+    /// Price an open position is valued, triggered and closed at: the side that would close it.
+    pub fn exit_price(&self, trade: &Trade) -> Option<i64> {
+        let closing_side = match trade.side {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        };
+        self.execution_price(&trade.asset, &closing_side)
+    }
+
+    pub fn asset_status(&self, asset: &str) -> Option<AssetStatus> {
+        self.assets.get(asset).map(|info| info.status)
+    }
+
+    /// Highest leverage allowed on `asset`
+    pub fn max_leverage(&self, asset: &str) -> i64 {
+        self.assets
+            .get(asset)
+            .and_then(|info| info.max_leverage)
+            .unwrap_or(CONFIG.max_leverage)
+    }
+
+    /// Admin halts in force for `user_id` trading `asset`, broadest scope first
+    pub fn active_halts(&self, user_id: &str, asset: &str) -> Vec<(HaltScope, &HaltState)> {
+        let mut halts = Vec::new();
+        if let Some(halt) = &self.global_halt {
+            halts.push((HaltScope::Global, halt));
+        }
+        if let Some(halt) = self.asset_halts.get(asset) {
+            halts.push((HaltScope::Asset, halt));
+        }
+        if let Some(halt) = self.user_halts.get(user_id) {
+            halts.push((HaltScope::User, halt));
+        }
+        halts
+    }
+
+    /// Whether trading on `asset` is currently halted
+    pub fn is_market_halted(&self, asset: &str) -> bool {
+        self.stale_markets.contains(asset)
+    }
 }
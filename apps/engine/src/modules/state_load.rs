@@ -0,0 +1,294 @@
+use crate::modules::financing::latest_rollover;
+use crate::modules::state::{EngineState, OrderBook};
+use crate::modules::terms::position_term;
+use crate::modules::types::{
+    HaltScope, HaltState, MarginMode, Order, OrderStatus, OrderType, PositionTerm, Side,
+    StateLoadHalt, StateLoadResponse, StateLoadTrade, Trade,
+};
+
+/// Apply one page of the DB's state-load answer: balances, margin modes, holdings and halts
+/// are taken as they are, OPEN rows go back on their order book, other live rows become open
+/// positions and the unfilled remainder a row records goes back on the book as well.
+pub fn apply_state_load_page(engine_state: &mut EngineState, page: &StateLoadResponse) {
+    for entry in &page.balances {
+        engine_state.load_balance(&entry.user_id, entry.balance);
+        if entry.margin_mode != MarginMode::default() {
+            engine_state
+                .margin_modes
+                .insert(entry.user_id.clone(), entry.margin_mode);
+        }
+    }
+    for entry in &page.holdings {
+        engine_state
+            .holdings
+            .insert((entry.user_id.clone(), entry.asset.clone()), entry.quantity);
+    }
+    for halt in &page.halts {
+        restore_halt(engine_state, halt);
+    }
+    for row in &page.trades {
+        let side = match row.side.as_str() {
+            "BUY" => Side::Buy,
+            "SELL" => Side::Sell,
+            other => {
+                eprintln!("Skipping loaded trade {}: unknown side {}", row.id, other);
+                continue;
+            }
+        };
+        match row.status.as_str() {
+            "OPEN" => {
+                let margin = row.locked_margin.unwrap_or(row.margin);
+                restore_resting_order(engine_state, row, side, row.quantity, margin);
+            }
+            "MATCHED" | "FILLED" => {
+                restore_position(engine_state, row, side.clone());
+                restore_remainder(engine_state, row, side);
+            }
+            // A closed position's row still carries its order's remainder
+            _ => restore_remainder(engine_state, row, side),
+        }
+    }
+}
+
+/// Finish a state load at `now`, once every page is applied.
+/// The DB doesn't record financing rollovers, so the most recent one is taken as charged: a
+/// rollover that fell due while the engine was down is skipped rather than risk charging
+/// positions twice for one the previous engine had already charged.
+pub fn complete_state_load(engine_state: &mut EngineState, now: i64) {
+    engine_state.last_financing_rollover = Some(latest_rollover(now));
+}
+
+fn restore_halt(engine_state: &mut EngineState, halt: &StateLoadHalt) {
+    let state = HaltState {
+        allow_cancels: halt.allow_cancels,
+        allow_closes: halt.allow_closes,
+        reason: halt.reason.clone(),
+        since: halt.since,
+    };
+    match (halt.scope, &halt.target) {
+        (HaltScope::Global, _) => engine_state.global_halt = Some(state),
+        (HaltScope::Asset, Some(asset)) => {
+            engine_state.asset_halts.insert(asset.clone(), state);
+        }
+        (HaltScope::User, Some(user_id)) => {
+            engine_state.user_halts.insert(user_id.clone(), state);
+        }
+        (scope, None) => eprintln!("Skipping loaded {:?} halt without a target", scope),
+    }
+}
+
+/// A loaded row's term with the expiry and rollover choice it was stored with. Rows stored
+/// before expiries were count the term from when they were created.
+fn stored_term(row: &StateLoadTrade) -> Option<PositionTerm> {
+    let term = position_term(row.trade_term, row.term_rollover, row.created_at)?;
+    Some(PositionTerm {
+        expires_at: row.term_expires_at.unwrap_or(term.expires_at),
+        ..term
+    })
+}
+
+/// Put back the unfilled remainder of a partially filled order, with the margin reserved for it.
+fn restore_remainder(engine_state: &mut EngineState, row: &StateLoadTrade, side: Side) {
+    if let Some(quantity) = row.resting_quantity.filter(|quantity| *quantity > 0) {
+        let margin = row.resting_margin.unwrap_or(0);
+        restore_resting_order(engine_state, row, side, quantity, margin);
+    }
+}
+
+fn restore_resting_order(
+    engine_state: &mut EngineState,
+    row: &StateLoadTrade,
+    side: Side,
+    quantity: i64,
+    margin: i64,
+) {
+    let Some(price) = row.limit_price.or(row.entry_price) else {
+        eprintln!("Skipping loaded order {}: no limit price", row.id);
+        return;
+    };
+    let order = Order {
+        id: row.id.clone(),
+        user_id: row.user_id.clone(),
+        asset: row.asset.clone(),
+        side: side.clone(),
+        order_type: OrderType::Limit,
+        price: Some(price),
+        quantity,
+        filled: 0,
+        status: OrderStatus::Open,
+        margin,
+        leverage: row.leverage,
+        stop_loss_percent: row.stop_loss_percent,
+        take_profit_percent: row.take_profit_percent,
+        created_at: row.created_at,
+        expiry: None,
+        term: stored_term(row),
+    };
+    let book = engine_state
+        .order_books
+        .entry(row.asset.clone())
+        .or_insert_with(OrderBook::new);
+    let levels = match side {
+        Side::Buy => &mut book.buy,
+        Side::Sell => &mut book.sell,
+    };
+    // Rows arrive oldest first, which keeps time priority within a price level
+    levels.entry(price).or_default().push_back(order);
+    engine_state.ledger.deposits += margin;
+}
+
+fn restore_position(engine_state: &mut EngineState, row: &StateLoadTrade, side: Side) {
+    let Some(entry_price) = row.entry_price else {
+        eprintln!("Skipping loaded position {}: no entry price", row.id);
+        return;
+    };
+    let (order_type, limit_price) = match row.order_type.as_str() {
+        "LIMIT" => (OrderType::Limit, row.limit_price),
+        _ => (OrderType::Market, None),
+    };
+    let trade = Trade {
+        id: row.id.clone(),
+        user_id: row.user_id.clone(),
+        asset: row.asset.clone(),
+        side,
+        margin: row.margin,
+        leverage: row.leverage,
+        quantity: row.quantity,
+        entry_price: Some(entry_price),
+        close_price: None,
+        pnl: None,
+        status: Some(OrderStatus::Filled.to_string()),
+        created_at: Some(row.created_at),
+        closed_at: None,
+        take_profit_percent: row.take_profit_percent,
+        stop_loss_percent: row.stop_loss_percent,
+        price: limit_price,
+        term: stored_term(row),
+        order_type: Some(order_type),
+        limit_price,
+    };
+    engine_state.open_trades.insert(row.id.clone(), trade);
+    let locked_margin = row.locked_margin.unwrap_or(row.margin);
+    engine_state.set_locked_margin(&row.id, locked_margin);
+    engine_state.ledger.deposits += locked_margin;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::invariants::check_funds;
+    use crate::modules::types::TradeTerm;
+
+    const HOUR_MS: i64 = 3_600_000;
+
+    /// A page as the DB sends it: amounts as strings, enums in the DB's casing.
+    fn page() -> StateLoadResponse {
+        serde_json::from_value(serde_json::json!({
+            "requestId": "load",
+            "balances": [
+                { "userId": "user", "balance": "1000", "marginMode": "cross" },
+                { "userId": "other", "balance": 500, "marginMode": "isolated" }
+            ],
+            "holdings": [{ "userId": "user", "asset": "BTC", "quantity": "-2" }],
+            "halts": [{
+                "scope": "asset", "target": "ETH", "allowCancels": true,
+                "allowCloses": false, "reason": "maintenance", "since": 5
+            }],
+            "trades": [
+                {
+                    "id": "resting", "userId": "other", "asset": "BTC", "side": "BUY",
+                    "status": "OPEN", "orderType": "LIMIT", "quantity": "3", "margin": "60",
+                    "leverage": 5, "limitPrice": "100", "createdAt": 1
+                },
+                {
+                    "id": "position", "userId": "user", "asset": "BTC", "side": "SELL",
+                    "status": "FILLED", "orderType": "LIMIT", "quantity": "2", "margin": "40",
+                    "lockedMargin": "35", "leverage": 5, "entryPrice": "110", "limitPrice": "110",
+                    "tradeTerm": "INTRADAY", "termRollover": true, "termExpiresAt": 7 * HOUR_MS,
+                    "restingQuantity": "1", "restingMargin": "20", "createdAt": 2
+                },
+                {
+                    "id": "legacy", "userId": "user", "asset": "BTC", "side": "BUY",
+                    "status": "MATCHED", "orderType": "MARKET", "quantity": "1", "margin": "10",
+                    "leverage": 2, "entryPrice": "105", "tradeTerm": "INTRAHOUR", "createdAt": 3
+                },
+                {
+                    "id": "closed", "userId": "other", "asset": "BTC", "side": "SELL",
+                    "status": "CLOSED", "orderType": "LIMIT", "quantity": "4", "margin": "80",
+                    "leverage": 5, "limitPrice": "120", "restingQuantity": "2",
+                    "restingMargin": "30", "createdAt": 4
+                }
+            ],
+            "done": true
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn accounts_and_halts_are_taken_as_stored() {
+        let mut engine_state = EngineState::new();
+        apply_state_load_page(&mut engine_state, &page());
+        assert_eq!(engine_state.balances["user"], 1_000);
+        assert_eq!(engine_state.margin_mode("user"), MarginMode::Cross);
+        assert!(!engine_state.margin_modes.contains_key("other"));
+        assert_eq!(
+            engine_state.holdings[&("user".to_string(), "BTC".to_string())],
+            -2
+        );
+        let halt = &engine_state.asset_halts["ETH"];
+        assert!(halt.allow_cancels && !halt.allow_closes);
+        assert_eq!(halt.since, 5);
+    }
+
+    #[test]
+    fn positions_keep_their_stored_terms_and_order_type() {
+        let mut engine_state = EngineState::new();
+        apply_state_load_page(&mut engine_state, &page());
+
+        let position = &engine_state.open_trades["position"];
+        assert_eq!(position.side, Side::Sell);
+        assert!(matches!(position.order_type, Some(OrderType::Limit)));
+        assert_eq!(position.limit_price, Some(110));
+        assert_eq!(
+            position.term,
+            Some(PositionTerm {
+                term: TradeTerm::Intraday,
+                expires_at: 7 * HOUR_MS,
+                rollover: true,
+            })
+        );
+        assert_eq!(engine_state.get_locked_margin_or("position", 0), 35);
+
+        // Rows stored before expiries were count the term from when they were created
+        let legacy = &engine_state.open_trades["legacy"];
+        assert!(matches!(legacy.order_type, Some(OrderType::Market)));
+        assert_eq!(legacy.term.unwrap().expires_at, 3 + HOUR_MS);
+        assert!(!legacy.term.unwrap().rollover);
+    }
+
+    #[test]
+    fn resting_orders_and_remainders_go_back_on_the_book() {
+        let mut engine_state = EngineState::new();
+        apply_state_load_page(&mut engine_state, &page());
+        complete_state_load(&mut engine_state, 10 * HOUR_MS);
+
+        let book = &engine_state.order_books["BTC"];
+        let resting = &book.buy[&100][0];
+        assert_eq!((resting.id.as_str(), resting.quantity), ("resting", 3));
+        assert_eq!(resting.margin, 60);
+        let remainder = &book.sell[&110][0];
+        assert_eq!((remainder.id.as_str(), remainder.quantity), ("position", 1));
+        assert_eq!(remainder.margin, 20);
+        assert_eq!(remainder.term.unwrap().expires_at, 7 * HOUR_MS);
+        let closed = &book.sell[&120][0];
+        assert_eq!((closed.id.as_str(), closed.quantity), ("closed", 2));
+        assert!(!engine_state.open_trades.contains_key("closed"));
+
+        // Every loaded amount is accounted for as a deposit
+        assert_eq!(check_funds(&engine_state).discrepancy(), 0);
+        assert_eq!(
+            engine_state.last_financing_rollover,
+            Some(latest_rollover(10 * HOUR_MS))
+        );
+    }
+}
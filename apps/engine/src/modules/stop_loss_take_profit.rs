@@ -1,23 +1,45 @@
-use crate::modules::liquidations::{check_liquidation, liquidate_trade};
-use crate::modules::pnl::calculate_pnl;
+use crate::kafka::producer;
+use crate::modules::liquidations::{
+    check_liquidation, cross_liquidation_queue, liquidate_cross_account, liquidate_trade,
+};
+use crate::modules::margin_calls::evaluate_margin_calls;
+use crate::modules::settlement::close_trade_at_price;
 use crate::modules::state::SharedEngineState;
-use crate::modules::types::Side;
+use crate::modules::types::{MarginMode, Side};
+use std::collections::HashSet;
 
+/// Close positions that hit their stop loss or take profit, liquidate under-margined ones and
+/// issue margin calls. Returns whether any of that changed state.
 pub async fn monitor_stop_loss_take_profit(
     state: SharedEngineState,
     tx: tokio::sync::mpsc::Sender<String>,
-) {
+    now: i64,
+) -> bool {
     let mut engine_state = state.lock().await;
+    let margin_calls_before = engine_state.margin_calls.clone();
 
     // Iterate through all open trades
     let mut to_close = Vec::new(); // Track trades to close
     let mut to_liquidate = Vec::new(); // Track trades to liquidate
+    let mut cross_users = HashSet::new(); // Cross-margin accounts checked on equity below
+    let mut paused_users = HashSet::new(); // Cross accounts holding a position on a halted market
 
     for (order_id, trade) in engine_state.open_trades.iter() {
-        if let Some(latest_price) = engine_state.prices.get(&trade.asset) {
-            let latest_price = *latest_price;
+        let is_cross = engine_state.margin_mode(&trade.user_id) == MarginMode::Cross;
+        // Never act on a price from a frozen feed
+        if engine_state.is_market_halted(&trade.asset) {
+            if is_cross {
+                paused_users.insert(trade.user_id.clone());
+            }
+            continue;
+        }
+        // Positions trigger on the side that would close them: longs on the bid, shorts on the ask
+        if let Some(latest_price) = engine_state.exit_price(trade) {
+            if is_cross {
+                cross_users.insert(trade.user_id.clone());
+            }
             // Check for liquidation first
-            if check_liquidation(trade, latest_price) {
+            if !is_cross && check_liquidation(trade, latest_price) {
                 println!("Liquidation triggered for order {}", order_id);
                 to_liquidate.push((order_id.clone(), latest_price));
                 continue;
@@ -31,7 +53,7 @@ pub async fn monitor_stop_loss_take_profit(
                         let take_profit_price = entry_price + (entry_price * tp / 100);
                         if latest_price >= take_profit_price {
                             println!("Take profit triggered for order {}", order_id);
-                            to_close.push(order_id.clone());
+                            to_close.push((order_id.clone(), latest_price, "take_profit"));
                             continue;
                         }
                     }
@@ -41,7 +63,7 @@ pub async fn monitor_stop_loss_take_profit(
                         let stop_loss_price = entry_price - (entry_price * sl / 100);
                         if latest_price <= stop_loss_price {
                             println!("Stop loss triggered for order {}", order_id);
-                            to_close.push(order_id.clone());
+                            to_close.push((order_id.clone(), latest_price, "stop_loss"));
                             continue;
                         }
                     }
@@ -52,7 +74,7 @@ pub async fn monitor_stop_loss_take_profit(
                         let take_profit_price = entry_price - (entry_price * tp / 100);
                         if latest_price <= take_profit_price {
                             println!("Take profit triggered for order {}", order_id);
-                            to_close.push(order_id.clone());
+                            to_close.push((order_id.clone(), latest_price, "take_profit"));
                             continue;
                         }
                     }
@@ -62,7 +84,7 @@ pub async fn monitor_stop_loss_take_profit(
                         let stop_loss_price = entry_price + (entry_price * sl / 100);
                         if latest_price >= stop_loss_price {
                             println!("Stop loss triggered for order {}", order_id);
-                            to_close.push(order_id.clone());
+                            to_close.push((order_id.clone(), latest_price, "stop_loss"));
                             continue;
                         }
                     }
@@ -71,23 +93,58 @@ pub async fn monitor_stop_loss_take_profit(
         }
     }
 
+    let mut changed = !to_liquidate.is_empty() || !to_close.is_empty();
+
     // Liquidate trades that fell below maintenance margin
     for (order_id, latest_price) in to_liquidate {
-        liquidate_trade(&mut engine_state, &order_id, latest_price);
+        if let Some(outcome) = liquidate_trade(&mut engine_state, &order_id, latest_price, now) {
+            if let Ok(json_string) = serde_json::to_string(&outcome) {
+                let _ = tx.send(json_string).await;
+            }
+        }
     }
 
-    // Close trades that hit stop loss or take profit
-    for order_id in to_close {
-        if let Some(mut trade) = engine_state.open_trades.remove(&order_id) {
-            // Set close_price for accurate PnL
-            if let Some(latest_price) = engine_state.prices.get(&trade.asset) {
-                trade.close_price = Some(*latest_price);
+    // Liquidate cross-margin accounts whose equity fell below total maintenance margin
+    for user_id in cross_users.difference(&paused_users) {
+        let queue = cross_liquidation_queue(&engine_state, user_id);
+        if queue.is_empty() {
+            continue;
+        }
+        changed = true;
+        println!(
+            "Cross margin liquidation triggered for user {} ({} positions)",
+            user_id,
+            queue.len()
+        );
+        for outcome in liquidate_cross_account(&mut engine_state, user_id, queue, now) {
+            if let Ok(json_string) = serde_json::to_string(&outcome) {
+                let _ = tx.send(json_string).await;
             }
-            let pnl = calculate_pnl(&trade);
-            if let Some(balance) = engine_state.balances.get_mut(&trade.user_id) {
-                *balance += pnl;
+        }
+    }
+
+    // Close trades that hit stop loss or take profit, releasing their margin
+    for (order_id, latest_price, reason) in to_close {
+        if let Some(outcome) = close_trade_at_price(
+            &mut engine_state,
+            &order_id,
+            latest_price,
+            "closed",
+            Some(reason),
+            false,
+            now,
+        ) {
+            if let Ok(json_string) = serde_json::to_string(&outcome) {
+                let _ = tx.send(json_string).await;
             }
-            println!("Closed order {} with PnL: {}", order_id, pnl);
         }
     }
+
+    // Warn positions and accounts approaching liquidation
+    for event in evaluate_margin_calls(&mut engine_state, now) {
+        if let Ok(json_string) = serde_json::to_string(&event) {
+            producer::publish_margin_call(&event.user_id, &json_string).await;
+        }
+    }
+    changed || engine_state.margin_calls != margin_calls_before
 }
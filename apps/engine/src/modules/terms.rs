@@ -0,0 +1,229 @@
+use crate::modules::config::CONFIG;
+use crate::modules::margin::position_update_outcome;
+use crate::modules::settlement::close_trade_at_price;
+use crate::modules::state::{EngineState, SharedEngineState};
+use crate::modules::types::{PositionTerm, TradeOutcome, TradeTerm};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+
+/// End of the term that starts at `from_ms`: an hour later for INTRAHOUR, otherwise the next
+/// UTC calendar boundary (midnight, Monday, first of the month or first of the year).
+pub fn term_end(term: TradeTerm, from_ms: i64) -> i64 {
+    let from = DateTime::<Utc>::from_timestamp_millis(from_ms).unwrap_or_else(Utc::now);
+    let date = from.date_naive();
+    let end = match term {
+        TradeTerm::Intrahour => return (from + Duration::hours(1)).timestamp_millis(),
+        TradeTerm::Intraday => date.succ_opt(),
+        TradeTerm::Week => {
+            Some(date + Duration::days(7 - date.weekday().num_days_from_monday() as i64))
+        }
+        TradeTerm::Month => {
+            if date.month() == 12 {
+                NaiveDate::from_ymd_opt(date.year() + 1, 1, 1)
+            } else {
+                NaiveDate::from_ymd_opt(date.year(), date.month() + 1, 1)
+            }
+        }
+        TradeTerm::Year => NaiveDate::from_ymd_opt(date.year() + 1, 1, 1),
+    };
+    end.map(|day| Utc.from_utc_datetime(&day.and_time(NaiveTime::MIN)))
+        .unwrap_or(from)
+        .timestamp_millis()
+}
+
+/// Term for a position opened at `opened_at`; the term is counted from the fill.
+pub fn position_term(
+    term: Option<TradeTerm>,
+    rollover: bool,
+    opened_at: i64,
+) -> Option<PositionTerm> {
+    term.map(|term| PositionTerm {
+        term,
+        expires_at: term_end(term, opened_at),
+        rollover,
+    })
+}
+
+/// An order's term restarted at `filled_at`, for the position its fill opens. A limit order
+/// can rest for longer than its term, which only starts running once it fills.
+pub fn start_term(term: Option<PositionTerm>, filled_at: i64) -> Option<PositionTerm> {
+    term.map(|term| PositionTerm {
+        expires_at: term_end(term.term, filled_at),
+        ..term
+    })
+}
+
+/// Close (or roll over) every position whose term has ended at `now`.
+/// Positions flagged for rollover are extended into the next term if the user can pay the
+/// rollover fee from their balance; everything else is closed at the current price.
+pub fn expire_terms(engine_state: &mut EngineState, now: i64) -> Vec<TradeOutcome> {
+    let mut expired: Vec<(String, PositionTerm, i64)> = engine_state
+        .open_trades
+        .iter()
+        .filter_map(|(id, trade)| {
+            let term = trade.term.filter(|term| term.expires_at <= now)?;
+            if engine_state.is_market_halted(&trade.asset) {
+                return None;
+            }
+            let price = engine_state.exit_price(trade)?;
+            Some((id.clone(), term, price))
+        })
+        .collect();
+    expired.sort_by(|a, b| a.1.expires_at.cmp(&b.1.expires_at).then(a.0.cmp(&b.0)));
+
+    let mut outcomes = Vec::new();
+    for (trade_id, term, price) in expired {
+        let trade = engine_state.open_trades[&trade_id].clone();
+        let notional = price as i128 * trade.quantity as i128 * trade.leverage as i128;
+        let fee = (notional * CONFIG.term_rollover_fee_bps as i128 / 10_000) as i64;
+        let balance = engine_state
+            .balances
+            .get(&trade.user_id)
+            .copied()
+            .unwrap_or(0);
+
+        if term.rollover && balance >= fee {
+            engine_state.charge_fee(&trade.user_id, fee);
+            let next_term = PositionTerm {
+                expires_at: term_end(term.term, term.expires_at),
+                ..term
+            };
+            if let Some(open_trade) = engine_state.open_trades.get_mut(&trade_id) {
+                open_trade.term = Some(next_term);
+            }
+            println!(
+                "Order {} rolled over to next {:?} term (fee {}), expires at {}",
+                trade_id, term.term, fee, next_term.expires_at
+            );
+            let mut outcome = position_update_outcome(
+                engine_state,
+                &engine_state.open_trades[&trade_id],
+                "term_rolled_over",
+                now,
+            );
+            outcome.fee = Some(fee);
+            outcomes.push(outcome);
+        } else if let Some(outcome) = close_trade_at_price(
+            engine_state,
+            &trade_id,
+            price,
+            "closed",
+            Some("term_expired"),
+            engine_state.caps_loss_at_margin(&trade.user_id),
+            now,
+        ) {
+            outcomes.push(outcome);
+        }
+    }
+    outcomes
+}
+
+/// Run the term expiry scheduler against shared state and publish the outcomes.
+/// Returns whether any term ended.
+pub async fn process_term_expiries(
+    state: SharedEngineState,
+    tx: tokio::sync::mpsc::Sender<String>,
+    now: i64,
+) -> bool {
+    let mut engine_state = state.lock().await;
+    let outcomes = expire_terms(&mut engine_state, now);
+    for outcome in &outcomes {
+        if let Ok(json_string) = serde_json::to_string(outcome) {
+            let _ = tx.send(json_string).await;
+        }
+    }
+    !outcomes.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::types::{Side, Trade};
+
+    const HOUR_MS: i64 = 3_600_000;
+
+    fn ms(date: &str) -> i64 {
+        DateTime::parse_from_rfc3339(date)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    fn state_with_position(term: PositionTerm, balance: i64) -> EngineState {
+        let mut engine_state = EngineState::new();
+        engine_state.balances.insert("user".to_string(), balance);
+        engine_state.prices.insert("BTC".to_string(), 100);
+        let trade = Trade {
+            id: "order".to_string(),
+            user_id: "user".to_string(),
+            asset: "BTC".to_string(),
+            side: Side::Buy,
+            margin: 100,
+            leverage: 10,
+            quantity: 10,
+            entry_price: Some(100),
+            close_price: None,
+            pnl: None,
+            status: None,
+            created_at: Some(0),
+            closed_at: None,
+            take_profit_percent: None,
+            stop_loss_percent: None,
+            price: None,
+            term: Some(term),
+            order_type: None,
+            limit_price: None,
+        };
+        engine_state.open_trades.insert(trade.id.clone(), trade);
+        engine_state.set_locked_margin("order", 100);
+        engine_state
+    }
+
+    #[test]
+    fn terms_end_on_the_next_calendar_boundary() {
+        // A Wednesday afternoon in December
+        let from = ms("2025-12-17T15:30:00Z");
+        assert_eq!(term_end(TradeTerm::Intrahour, from), from + HOUR_MS);
+        assert_eq!(
+            term_end(TradeTerm::Intraday, from),
+            ms("2025-12-18T00:00:00Z")
+        );
+        assert_eq!(term_end(TradeTerm::Week, from), ms("2025-12-22T00:00:00Z"));
+        assert_eq!(term_end(TradeTerm::Month, from), ms("2026-01-01T00:00:00Z"));
+        assert_eq!(term_end(TradeTerm::Year, from), ms("2026-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn a_term_starts_when_its_order_fills() {
+        let placed = position_term(Some(TradeTerm::Intrahour), true, 0);
+        let started = start_term(placed, 5 * HOUR_MS).unwrap();
+        assert_eq!(started.expires_at, 6 * HOUR_MS);
+        assert!(started.rollover);
+        assert_eq!(start_term(None, 5 * HOUR_MS), None);
+    }
+
+    #[test]
+    fn expired_terms_roll_over_when_the_fee_is_covered() {
+        let term = position_term(Some(TradeTerm::Intrahour), true, 0).unwrap();
+        let mut engine_state = state_with_position(term, 1_000);
+        assert!(expire_terms(&mut engine_state, HOUR_MS - 1).is_empty());
+
+        let outcomes = expire_terms(&mut engine_state, HOUR_MS);
+        // 10 bps of a 10_000 notional
+        assert_eq!(outcomes[0].reason.as_deref(), Some("term_rolled_over"));
+        assert_eq!(outcomes[0].fee, Some(10));
+        assert_eq!(engine_state.balances["user"], 990);
+        let rolled = engine_state.open_trades["order"].term.unwrap();
+        assert_eq!(rolled.expires_at, 2 * HOUR_MS);
+    }
+
+    #[test]
+    fn expired_terms_close_without_rollover_or_the_fee() {
+        for (rollover, balance) in [(false, 1_000), (true, 5)] {
+            let term = position_term(Some(TradeTerm::Intrahour), rollover, 0).unwrap();
+            let mut engine_state = state_with_position(term, balance);
+            let outcomes = expire_terms(&mut engine_state, HOUR_MS);
+            assert_eq!(outcomes[0].reason.as_deref(), Some("term_expired"));
+            assert!(engine_state.open_trades.is_empty());
+            assert_eq!(engine_state.balances["user"], balance + 100);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, VecDeque};
 use std::fmt;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -38,6 +39,33 @@ impl fmt::Display for OrderStatus {
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MarginMode {
+    #[default]
+    Isolated, // margin and liquidation tracked per position
+    Cross, // positions share the account balance; liquidation checks account equity
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TradeTerm {
+    Intrahour,
+    Intraday,
+    Week,
+    Month,
+    Year,
+}
+
+/// Term attached to a position: when it expires and whether it rolls over instead of closing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionTerm {
+    pub term: TradeTerm,
+    pub expires_at: i64, // ms since epoch
+    pub rollover: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateTradeRequest {
@@ -52,7 +80,8 @@ pub struct CreateTradeRequest {
     pub limit_price: Option<i64>,
     pub stop_loss_percent: Option<i64>,
     pub take_profit_percent: Option<i64>,
-    pub trade_term: Option<String>,
+    pub trade_term: Option<TradeTerm>, // "INTRAHOUR" | "INTRADAY" | "WEEK" | "MONTH" | "YEAR"
+    pub term_rollover: Option<bool>,   // roll over for a fee instead of closing at term end
     pub time_in_force: Option<String>,
     pub expiry_timestamp: Option<i64>, // ms since epoch
     pub timestamp: i64,
@@ -77,14 +106,28 @@ pub struct Order {
     pub take_profit_percent: Option<i64>,
     pub created_at: i64,
     pub expiry: Option<i64>,
+    pub term: Option<PositionTerm>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PriceUpdate {
     pub asset: String,
-    pub price: i64,
+    pub price: i64, // mid
+    pub bid: i64,
+    pub ask: i64,
     pub timestamp: i64,
+    pub source: String, // feed the tick came from, e.g. "backpack"
+}
+
+/// A filtered tick waiting for the sequencer. Ticks from the same source that arrive before it
+/// is taken replace it, but their prices still count towards the candles' range.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StagedTick {
+    pub update: PriceUpdate,
+    pub high: i64, // highest price among the ticks folded into this one
+    pub low: i64,  // lowest price among them
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -106,6 +149,9 @@ pub struct Trade {
     pub take_profit_percent: Option<i64>,
     pub stop_loss_percent: Option<i64>,
     pub price: Option<i64>,
+    pub term: Option<PositionTerm>,
+    pub order_type: Option<OrderType>, // type of the order that opened the position
+    pub limit_price: Option<i64>,      // that order's limit price, for limit orders
 }
 
 pub fn order_to_trade(order: &Order) -> Trade {
@@ -126,9 +172,16 @@ pub fn order_to_trade(order: &Order) -> Trade {
         take_profit_percent: order.take_profit_percent,
         stop_loss_percent: order.stop_loss_percent,
         price: order.price,
+        term: order.term,
+        order_type: Some(order.order_type.clone()),
+        limit_price: match order.order_type {
+            OrderType::Limit => order.price,
+            OrderType::Market => None,
+        },
     }
 }
 
+#[allow(dead_code)]
 pub fn trade_to_order(trade: &Trade) -> Order {
     Order {
         id: trade.id.clone(),
@@ -146,6 +199,7 @@ pub fn trade_to_order(trade: &Trade) -> Order {
         take_profit_percent: trade.take_profit_percent,
         created_at: trade.created_at.unwrap_or(0),
         expiry: None,
+        term: trade.term,
     }
 }
 
@@ -172,27 +226,519 @@ pub struct TradeOutcome {
     pub updated_balance: Option<i64>,
     pub updated_holdings: Option<i64>,
     pub locked_margin: Option<i64>,
+    pub liquidation_price: Option<i64>, // set while the position remains open
+    pub fee: Option<i64>,               // fee charged with this update, e.g. a term rollover
+    pub term: Option<PositionTerm>,     // the position's term as of this update
+    pub resting_quantity: Option<i64>,  // unfilled part of the order still on the book, if known
+    pub resting_margin: Option<i64>,    // margin reserved for that part
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CloseTradeRequest {
     pub user_id: String,
+    pub correlation_id: Option<String>,
     pub order_id: String,
+    #[allow(dead_code)]
     pub close_price: Option<i64>, // engine provides when closing
     pub reason: Option<String>,
     pub timestamp: i64,
 }
 
-#[derive(Debug, Clone)]
-pub struct OrderBook {
-    pub buy: std::collections::BTreeMap<i64, std::collections::VecDeque<Order>>, // Buy orders sorted by price
-    pub sell: std::collections::BTreeMap<i64, std::collections::VecDeque<Order>>, // Sell orders sorted by price
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarginModeRequest {
+    pub user_id: String,
+    pub correlation_id: Option<String>,
+    pub margin_mode: MarginMode, // "isolated" | "cross"
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MarginAction {
+    Add,
+    Remove,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionMarginRequest {
+    pub user_id: String,
+    pub correlation_id: Option<String>,
+    pub order_id: String,     // open position to adjust
+    pub action: MarginAction, // "add" | "remove"
+    pub amount: i64,          // scaled integer, always positive
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeverageChangeRequest {
+    pub user_id: String,
+    pub correlation_id: Option<String>,
+    pub order_id: String, // open position to adjust
+    pub leverage: i64,    // requested leverage
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarginCallEvent {
+    pub scope: String, // "position" | "account"
+    pub user_id: String,
+    pub trade_id: Option<String>, // set for isolated positions
+    pub asset: Option<String>,
+    pub status: String,            // "triggered" | "cleared"
+    pub threshold_percent: i64,    // margin call level crossed
+    pub margin_level_percent: i64, // current margin (or equity) as % of maintenance margin
+    pub margin: i64,               // position margin plus unrealized PnL, or account equity
+    pub maintenance_margin: i64,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FinancingCharge {
+    pub trade_id: String,
+    pub asset: String,
+    pub side: Side,
+    pub quantity: i64,
+    pub leverage: i64,
+    pub price: i64,
+    pub notional: i64,
+    pub rate_bps: i64,
+    pub amount: i64,              // credited to the user; negative when charged
+    pub from_margin: i64,         // part of a charge taken from the position margin
+    pub from_insurance_fund: i64, // part neither the balance nor the margin could cover
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FinancingEvent {
+    pub user_id: String,
+    pub rollover_at: i64, // ms since epoch of the rollover being charged
+    pub total_amount: i64,
+    pub charges: Vec<FinancingCharge>,
+    pub updated_balance: Option<i64>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketStatusEvent {
+    pub asset: String,
+    pub status: String, // "halted" | "trading"
+    pub reason: String, // e.g. "stale_price", "price_resumed"
+    pub last_price_at: Option<i64>,
+    pub timestamp: i64,
+}
+
+/// A tick outside the deviation band, held back until enough consecutive ticks confirm it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantinedPrice {
+    pub price: i64,
+    pub confirmations: i64,
+}
+
+/// Per-asset counts of price ticks dropped by the feed filter.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceRejections {
+    pub out_of_order: u64,
+    pub non_positive: u64,
+    pub crossed: u64,
+    pub quarantined: u64,
+}
+
+/// Top of book for an asset. Buys execute on the ask and sells on the bid.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quote {
+    pub bid: i64,
+    pub ask: i64,
+    pub mid: i64,
+}
+
+/// Last accepted quote from one price source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceQuote {
+    pub source: String,
+    pub price: i64, // mid
+    pub bid: i64,
+    pub ask: i64,
+    pub timestamp: i64,   // feed timestamp
+    pub received_at: i64, // engine clock when accepted
+}
+
+/// Price the engine used for an asset and the fresh quotes it was derived from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AggregatedPrice {
+    pub asset: String,
+    pub price: i64, // mid
+    pub bid: i64,
+    pub ask: i64,
+    pub sources: Vec<SourceQuote>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceQueryRequest {
+    pub correlation_id: Option<String>,
+    pub asset: String,
+    pub at: Option<i64>, // ms since epoch; latest price if absent
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CandleInterval {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl CandleInterval {
+    pub const ALL: [CandleInterval; 4] = [
+        CandleInterval::OneMinute,
+        CandleInterval::FiveMinutes,
+        CandleInterval::OneHour,
+        CandleInterval::OneDay,
+    ];
+
+    pub fn duration_ms(self) -> i64 {
+        match self {
+            CandleInterval::OneMinute => 60_000,
+            CandleInterval::FiveMinutes => 300_000,
+            CandleInterval::OneHour => 3_600_000,
+            CandleInterval::OneDay => 86_400_000,
+        }
+    }
+}
+
+/// OHLC from price ticks plus executed volume for one asset and interval.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Candle {
+    pub asset: String,
+    pub interval: CandleInterval,
+    pub open_time: i64,  // ms since epoch, inclusive
+    pub close_time: i64, // ms since epoch, exclusive
+    pub open: i64,
+    pub high: i64,
+    pub low: i64,
+    pub close: i64,
+    pub volume: i64, // executed quantity
+    pub trades: i64, // number of fills
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CandleQueryRequest {
+    pub correlation_id: Option<String>,
+    pub asset: String,
+    pub interval: CandleInterval,
+    pub limit: Option<usize>, // most recent closed candles to return; all kept if absent
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetStatus {
+    Trading,
+    Suspended, // no new orders; open positions keep being managed
+    Delisted,
+}
+
+impl AssetStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AssetStatus::Trading => "trading",
+            AssetStatus::Suspended => "suspended",
+            AssetStatus::Delisted => "delisted",
+        }
+    }
+}
+
+/// Registry entry for a tradable asset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetInfo {
+    pub status: AssetStatus,
+    pub max_leverage: Option<i64>, // engine-wide max leverage if absent
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    tag = "action",
+    rename_all = "snake_case",
+    rename_all_fields = "camelCase"
+)]
+pub enum AdminCommand {
+    Add {
+        asset: String,
+        max_leverage: Option<i64>,
+    },
+    Suspend {
+        asset: String,
+    },
+    Resume {
+        asset: String,
+    },
+    Delist {
+        asset: String,
+        settlement_price: i64, // final price every open position is closed at
+    },
+    Halt {
+        scope: HaltScope,
+        target: Option<String>, // asset or user id; none for a global halt
+        #[serde(default)]
+        allow_cancels: bool,
+        #[serde(default)]
+        allow_closes: bool,
+        reason: Option<String>,
+    },
+    LiftHalt {
+        scope: HaltScope,
+        target: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HaltScope {
+    Global,
+    Asset,
+    User,
+}
+
+/// An admin trading halt. New orders are always rejected; cancels and closes only when allowed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HaltState {
+    pub allow_cancels: bool,
+    pub allow_closes: bool,
+    pub reason: Option<String>,
+    pub since: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HaltEvent {
+    pub scope: HaltScope,
+    pub target: Option<String>,
+    pub status: String, // "halted" | "updated" | "lifted"
+    pub allow_cancels: bool,
+    pub allow_closes: bool,
+    pub reason: Option<String>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminRequest {
+    pub correlation_id: Option<String>,
+    #[serde(flatten)]
+    pub command: AdminCommand,
+    pub timestamp: i64,
 }
 
-#[derive(Debug, Clone)]
-pub struct EngineState {
-    pub balances: std::collections::HashMap<String, i64>, // User balances
-    pub order_books: std::collections::HashMap<String, OrderBook>, // Asset -> OrderBook
-    pub open_orders: std::collections::HashMap<String, Order>, // Order ID -> Order
+/// Running totals of money that entered or left user funds. Together with balances and
+/// margin they must always balance: see `modules::invariants`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Ledger {
+    pub deposits: i64,       // balances and margin loaded from the DB
+    pub adjustments: i64,    // drift: DB reloads and corrections that replaced a held balance
+    pub realized_pnl: i64,   // PnL credited to (or debited from) balances on closes
+    pub fees: i64,           // financing and rollover fees collected, net of credits
+    pub insurance_fund: i64, // net of shortfalls absorbed when floored accounts go negative
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountLoadStatus {
+    #[default]
+    Unloaded, // nothing requested yet
+    Loading, // requests in flight; new orders queue behind them
+    Ready,   // everything the queued orders needed has arrived
+    Failed,  // the DB didn't answer in time; the next order asks again
+}
+
+/// Loading of a user's balance and holdings from the DB, with the orders waiting on it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountLoad {
+    pub status: AccountLoadStatus,
+    pub started_at: i64, // when the current load began; its deadline counts from here
+    pub balance_requested: bool, // balance request in flight
+    pub holdings_requested: BTreeSet<String>, // assets with a holdings request in flight
+    pub queued: VecDeque<CreateTradeRequest>, // orders waiting, oldest first
+}
+
+impl AccountLoad {
+    pub fn awaiting_answers(&self) -> bool {
+        self.balance_requested || !self.holdings_requested.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReconciliationKind {
+    Balance,
+    Holdings,
+}
+
+/// An engine value checked against the database: a user's balance, or their holdings of an asset.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconciliationKey {
+    pub kind: ReconciliationKind,
+    pub user_id: String,
+    pub asset: Option<String>, // holdings only
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconciliationMismatch {
+    #[serde(flatten)]
+    pub key: ReconciliationKey,
+    pub engine: i64,
+    pub database: i64,
+    pub difference: i64, // database - engine
+    pub corrected: bool, // engine took the database value
+}
+
+/// A reconciliation in progress: requests sent, answers still awaited.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconciliationRun {
+    pub run_id: String,
+    pub started_at: i64,
+    pub awaiting: BTreeSet<ReconciliationKey>,
+    pub checked: usize,
+    pub mismatches: Vec<ReconciliationMismatch>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconciliationReport {
+    pub run_id: String,
+    pub started_at: i64,
+    pub completed_at: i64,
+    pub checked: usize,
+    pub mismatches: Vec<ReconciliationMismatch>,
+    pub missing: Vec<ReconciliationKey>, // never answered before the run was closed
+}
+
+/// One page of the DB's answer to an engine state-load request. Amounts arrive as strings
+/// since the DB stores them as BigInt.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateLoadResponse {
+    pub request_id: String,
+    #[serde(default)]
+    pub balances: Vec<StateLoadBalance>,
+    #[serde(default)]
+    pub trades: Vec<StateLoadTrade>,
+    #[serde(default)]
+    pub holdings: Vec<StateLoadHoldings>,
+    #[serde(default)]
+    pub halts: Vec<StateLoadHalt>,
+    pub done: bool, // last page
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateLoadBalance {
+    pub user_id: String,
+    #[serde(deserialize_with = "scaled_int")]
+    pub balance: i64,
+    #[serde(default)]
+    pub margin_mode: MarginMode,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateLoadHoldings {
+    pub user_id: String,
+    pub asset: String,
+    #[serde(deserialize_with = "scaled_int")]
+    pub quantity: i64,
+}
+
+/// An admin halt still in force, as recorded from the "trading-halt" topic.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateLoadHalt {
+    pub scope: HaltScope,
+    pub target: Option<String>, // asset or user id; none for a global halt
+    pub allow_cancels: bool,
+    pub allow_closes: bool,
+    pub reason: Option<String>,
+    pub since: i64, // ms since epoch
+}
+
+/// A DB trade row that is still live: a resting limit order (status OPEN) or an open position.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateLoadTrade {
+    pub id: String,
+    pub user_id: String,
+    pub asset: String,
+    pub side: String,       // "BUY" | "SELL"
+    pub status: String,     // "OPEN" | "MATCHED" | "FILLED"
+    pub order_type: String, // "MARKET" | "LIMIT"
+    #[serde(deserialize_with = "scaled_int")]
+    pub quantity: i64,
+    #[serde(deserialize_with = "scaled_int")]
+    pub margin: i64,
+    #[serde(default, deserialize_with = "scaled_int_opt")]
+    pub locked_margin: Option<i64>,
+    pub leverage: i64,
+    #[serde(default, deserialize_with = "scaled_int_opt")]
+    pub entry_price: Option<i64>,
+    #[serde(default, deserialize_with = "scaled_int_opt")]
+    pub limit_price: Option<i64>,
+    pub stop_loss_percent: Option<i64>,
+    pub take_profit_percent: Option<i64>,
+    pub trade_term: Option<TradeTerm>,
+    #[serde(default)]
+    pub term_rollover: bool,
+    pub term_expires_at: Option<i64>, // ms since epoch; absent on rows written before terms were stored
+    #[serde(default, deserialize_with = "scaled_int_opt")]
+    pub resting_quantity: Option<i64>, // unfilled remainder of a partially filled order
+    #[serde(default, deserialize_with = "scaled_int_opt")]
+    pub resting_margin: Option<i64>, // margin reserved for that remainder
+    pub created_at: i64,              // ms since epoch
+}
+
+/// A scaled integer sent either as a number or as a numeric string.
+fn scaled_int<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    scaled_int_opt(deserializer)?.ok_or_else(|| serde::de::Error::custom("missing amount"))
+}
+
+fn scaled_int_opt<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<i64>, D::Error> {
+    match Option::<serde_json::Value>::deserialize(deserializer)? {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(serde_json::Value::Number(num)) => num
+            .as_i64()
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom("amount out of range")),
+        Some(serde_json::Value::String(raw)) => {
+            raw.parse().map(Some).map_err(serde::de::Error::custom)
+        }
+        Some(other) => Err(serde::de::Error::custom(format!(
+            "expected an amount, got {}",
+            other
+        ))),
+    }
 }
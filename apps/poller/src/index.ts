@@ -1,18 +1,26 @@
 import WebSocket from 'ws';
-import { BACKPACK_WS_URL, ASSETS } from './config';
+import { BACKPACK_WS_URL, ASSETS, PRICE_SOURCE } from './config';
 import Big from 'big.js';
 import { producer } from '@repo/kafka';
 import { PriceUpdateSchema } from '@repo/schemas';
 
 let reconnectDelay = 1000;
 
-const publishPrice = (symbol: string, midPriceCents: number) => {
-    const payload = { asset: symbol, price: midPriceCents, timestamp: Date.now() };
+const publishPrice = (symbol: string, midPriceCents: number, bidCents: number, askCents: number) => {
+    const payload = {
+        asset: symbol,
+        price: midPriceCents,
+        bid: bidCents,
+        ask: askCents,
+        timestamp: Date.now(),
+        source: PRICE_SOURCE,
+    };
     const validationResult = PriceUpdateSchema.safeParse(payload);
     if (validationResult.success) {
         producer.send({
             topic: 'price-updates',
-            messages: [{ value: JSON.stringify(payload) }],
+            // Keyed by asset so each asset's ticks share a partition, in order
+            messages: [{ key: symbol, value: JSON.stringify(payload) }],
         }).catch(console.error);
     } else {
         console.error("Invalid schema:", validationResult.error);
@@ -48,28 +56,30 @@ const connect = () => {
             const ask = new Big(parsedMsg.data.a);
             const bid = new Big(parsedMsg.data.b);
             const midPriceCents = ask.plus(bid).div(2).times(100).round(0, Big.roundHalfUp).toNumber();
+            const bidCents = bid.times(100).round(0, Big.roundDown).toNumber();
+            const askCents = ask.times(100).round(0, Big.roundUp).toNumber();
 
             // Update the price variables and log
             switch (symbol) {
                 case "SOL_USDC":
                     //console.log(`UPDATE ==> SOL Midpoint Price: $${midPrice}`);
-                    publishPrice(symbol, midPriceCents);
+                    publishPrice(symbol, midPriceCents, bidCents, askCents);
                     break;
                 case "BTC_USDC":
                     //console.log(`UPDATE ==> BTC Midpoint Price: $${midPrice}`);
-                    publishPrice(symbol, midPriceCents);
+                    publishPrice(symbol, midPriceCents, bidCents, askCents);
                     break;
                 case "ETH_USDC":
                     //console.log(`UPDATE ==> ETH Midpoint Price: $${midPrice}`);
-                    publishPrice(symbol, midPriceCents);
+                    publishPrice(symbol, midPriceCents, bidCents, askCents);
                     break;
                 case "DOGE_USDC":
                     //console.log(`UPDATE ==> DOGE Midpoint Price: $${midPrice}`);
-                    publishPrice(symbol, midPriceCents);
+                    publishPrice(symbol, midPriceCents, bidCents, askCents);
                     break;
                 case "BNB_USDC":
                     //console.log(`UPDATE ==> BNB Midpoint Price: $${midPrice}`);
-                    publishPrice(symbol, midPriceCents);
+                    publishPrice(symbol, midPriceCents, bidCents, askCents);
                     break;
             }
         }
@@ -0,0 +1,24 @@
+-- CreateEnum
+CREATE TYPE "MarginMode" AS ENUM ('ISOLATED', 'CROSS');
+
+-- CreateEnum
+CREATE TYPE "HaltScope" AS ENUM ('GLOBAL', 'ASSET', 'USER');
+
+-- AlterTable
+ALTER TABLE "Balance" ADD COLUMN     "marginMode" "MarginMode" NOT NULL DEFAULT 'ISOLATED';
+
+-- CreateTable
+CREATE TABLE "TradingHalt" (
+    "id" TEXT NOT NULL,
+    "scope" "HaltScope" NOT NULL,
+    "target" TEXT NOT NULL,
+    "allowCancels" BOOLEAN NOT NULL,
+    "allowCloses" BOOLEAN NOT NULL,
+    "reason" TEXT,
+    "since" TIMESTAMP(3) NOT NULL,
+
+    CONSTRAINT "TradingHalt_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "TradingHalt_scope_target_key" ON "TradingHalt"("scope", "target");
@@ -0,0 +1,3 @@
+-- AlterTable
+ALTER TABLE "Trade" ADD COLUMN     "termExpiresAt" TIMESTAMP(3),
+ADD COLUMN     "termRollover" BOOLEAN NOT NULL DEFAULT false;
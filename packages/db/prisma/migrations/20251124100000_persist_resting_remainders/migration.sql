@@ -0,0 +1,3 @@
+-- AlterTable
+ALTER TABLE "Trade" ADD COLUMN     "restingMargin" BIGINT,
+ADD COLUMN     "restingQuantity" BIGINT;
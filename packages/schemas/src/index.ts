@@ -43,7 +43,10 @@ export const VerifyOtpSchema = z.object({
 export const PriceUpdateSchema = z.object({
   asset: z.string(),
   price: z.number().int(),
+  bid: z.optional(z.number().int()),
+  ask: z.optional(z.number().int()),
   timestamp: z.number().int(),
+  source: z.optional(z.string()),
 })
 
 export const createOrderSchema = z.object({
@@ -58,6 +61,8 @@ export const createOrderSchema = z.object({
   stopLossPercent: z.optional(z.number().int()),
   takeProfitPercent: z.optional(z.number().int()),
   tradeTerm: z.optional(z.enum(["INTRAHOUR", "INTRADAY", "WEEK", "MONTH", "YEAR"])),
+  // roll the position into the next term for a fee instead of closing it at term end
+  termRollover: z.optional(z.boolean()),
   quantity: z.optional(z.number().int().positive("Quantity must be a positive integer")),
   timeInForce: z.optional(z.enum(["IOC", "FOK", "DAY", "GTC", "EXPIRE_AT"])),
   expiryTimestamp: z.optional(z.number().int().nonnegative("expiryTimestamp must be a non-negative integer")),
@@ -109,3 +114,17 @@ export const closeOrderSchema = z.object({
   orderId: z.string().uuid("Invalid order ID"),  // Assuming UUID format
 });
 
+export const marginModeSchema = z.object({
+  marginMode: z.enum(["isolated", "cross"]),
+});
+
+export const positionMarginSchema = z.object({
+  orderId: z.string().uuid("Invalid order ID"),
+  action: z.enum(["add", "remove"]),
+  amount: z.number().int().positive("Amount must be a positive integer"),
+});
+
+export const positionLeverageSchema = z.object({
+  orderId: z.string().uuid("Invalid order ID"),
+  leverage: z.number().int().positive("Leverage must be a positive integer"),
+});